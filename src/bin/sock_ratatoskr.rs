@@ -2,23 +2,24 @@ use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 use chrono::Utc;
-use ratatoskr::utils::{log_to_file, write_json_atomic};
+use ratatoskr::utils::{log_to_file, resolve_icon, write_json_atomic};
 
 use std::fs;
 
-use ratatoskr::{DiskStats, EmbeddedDisplayStats, NetworkStats, RamStats, SystemStats};
+use ratatoskr::{AudioUpdate, BluezStats, CpuFreqStats, DiskStats, EmbeddedDisplayStats, FanStats, LedStats, MemoryDetailStats, NetworkStats, PowerProfileStats, PressureResource, PressureStats, RamStats, SystemStats};
 use ratatoskr::sysutils::*;
 
 use std::sync::{mpsc};
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::io::{Read, Write};
 use ratatoskr::config::Config;
 
 fn send_burst (s: &SystemStats, tx: mpsc::Sender<String>) {
     println!("About to send burst");
 
     // Sending only resources with a pooling time longer than 1s
-    let fields: [(&str, serde_json::Value); 10] = [
+    let fields: [(&str, serde_json::Value); 24] = [
         ("ram", serde_json::json!(s.ram)),
         ("disk", serde_json::json!(s.disk)),
         ("temperature", serde_json::json!(s.temperature)),
@@ -28,7 +29,21 @@ fn send_burst (s: &SystemStats, tx: mpsc::Sender<String>) {
         ("battery", serde_json::json!(s.battery)),
         ("network", serde_json::json!(s.network)),
         ("display", serde_json::json!(s.display)),
-        ("bluetooth", serde_json::json!(s.bluetooth_batteries))
+        ("bt-batteries", serde_json::json!(s.bluetooth_batteries)),
+        ("bluetooth", serde_json::json!(s.bluetooth)),
+        ("fans", serde_json::json!(s.fans)),
+        ("cpufreq", serde_json::json!(s.cpufreq)),
+        ("pressure", serde_json::json!(s.pressure)),
+        ("memory_detail", serde_json::json!(s.memory_detail)),
+        ("leds", serde_json::json!(s.leds)),
+        ("microphone", serde_json::json!(s.microphone)),
+        ("audio_streams", serde_json::json!(s.audio_streams)),
+        ("media", serde_json::json!(s.media)),
+        ("power_profile", serde_json::json!(s.power_profile)),
+        ("sun", serde_json::json!(s.sun)),
+        ("workspaces", serde_json::json!(s.workspaces)),
+        ("windows", serde_json::json!(s.windows)),
+        ("focused_window", serde_json::json!(s.focused_window))
 
     ];
 
@@ -45,6 +60,9 @@ fn send_burst (s: &SystemStats, tx: mpsc::Sender<String>) {
     println!("Burst sent");
 }
 
+// Write half of a connected client, locked for a whole message
+type Client = Arc<Mutex<UnixStream>>;
+
 pub fn start_socket_dispatcher(
     sock_path: &str,
     s: Arc<Mutex<SystemStats>>
@@ -52,7 +70,7 @@ pub fn start_socket_dispatcher(
     let _ = fs::remove_file(sock_path);
     let listener = UnixListener::bind(sock_path)?;
     listener.set_nonblocking(true)?;
-    let clients = Arc::new(Mutex::new(Vec::<UnixStream>::new()));
+    let clients = Arc::new(Mutex::new(Vec::<Client>::new()));
 
     let (tx, rx) = mpsc::channel::<String>();
     let clients_accept = Arc::clone(&clients);
@@ -65,7 +83,12 @@ pub fn start_socket_dispatcher(
                 Ok((stream, _)) => {
                     println!("{} New client connected", chrono::Local::now().format("%H:%M:%S%.3f"));
                     stream.set_nonblocking(true).ok();
-                    clients_accept.lock().unwrap().push(stream);
+                    let reader = stream.try_clone();
+                    let client = Arc::new(Mutex::new(stream));
+                    if let Ok(reader) = reader {
+                        spawn_client_reader(reader, Arc::clone(&client));
+                    }
+                    clients_accept.lock().unwrap().push(client);
                     println!("About to lock s and send burst");
                     if let Ok(data) = s.lock() {
                         // thread::sleep(Duration::from_millis(2000));
@@ -87,22 +110,130 @@ pub fn start_socket_dispatcher(
     thread::spawn(move || {
         for msg in rx {
             // eprintln!("msg in rx {:?}", msg);
-            let mut lock = clients_send.lock().unwrap();
-            // eprintln!("rx len {}, sending msg {:?}", lock.len(), msg);
-            lock.retain_mut(|c| {
-                // eprintln!("lock.retain_mut");
-                if let Err(e) = c.write_all(format!("{}\n", msg).as_bytes()) {
+            // Written outside the list lock, accepting clients never waits on a write
+            let snapshot: Vec<Client> = clients_send.lock().unwrap().clone();
+            // eprintln!("rx len {}, sending msg {:?}", snapshot.len(), msg);
+            let disconnected: Vec<Client> = snapshot.into_iter().filter(|c| {
+                if let Err(e) = c.lock().unwrap().write_all(format!("{}\n", msg).as_bytes()) {
                     eprintln!("Disconnected client ({e})");
-                    return false;
+                    return true;
                 }
-                true
-            });
+                false
+            }).collect();
+            if !disconnected.is_empty() {
+                clients_send.lock().unwrap().retain(|c| !disconnected.iter().any(|d| Arc::ptr_eq(c, d)));
+            }
         }
     });
 
     Ok(tx)
 }
 
+// Reads the requests of a client, one JSON object per line
+fn spawn_client_reader(mut stream: UnixStream, client: Client) {
+    thread::spawn(move || {
+        let mut pending: Vec<u8> = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    pending.extend_from_slice(&buf[..n]);
+                    while let Some(pos) = pending.iter().position(|b| *b == b'\n') {
+                        let line: Vec<u8> = pending.drain(..=pos).collect();
+                        let Some(reply) = handle_request(&String::from_utf8_lossy(&line)) else { continue };
+                        // Under the client's own lock, so the reply is not interleaved with a broadcast
+                        let mut writer = client.lock().unwrap();
+                        if let Err(e) = write_all_nonblocking(&mut writer, format!("{}\n", reply).as_bytes()) {
+                            // The dispatcher drops it at its next write
+                            eprintln!("Reply not sent, closing client ({e})");
+                            stream.shutdown(std::net::Shutdown::Both).ok();
+                            return;
+                        }
+                    }
+                }
+                // The stream is shared with the dispatcher, which needs it non-blocking
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    if wait_fd(&stream, libc::POLLIN, -1).is_err() {
+                        break;
+                    }
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(_) => break
+            }
+        }
+    });
+}
+
+// A client not reading its replies is closed after this. Broadcasts to it wait meanwhile,
+// while they drop a client at once when its buffer is full
+const REPLY_TIMEOUT_MS: i32 = 1000;
+
+fn write_all_nonblocking(stream: &mut UnixStream, mut buf: &[u8]) -> std::io::Result<()> {
+    while !buf.is_empty() {
+        match stream.write(buf) {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(n) => buf = &buf[n..],
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => wait_fd(stream, libc::POLLOUT, REPLY_TIMEOUT_MS)?,
+            Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e)
+        }
+    }
+    Ok(())
+}
+
+// Waits with poll(2) until the stream is ready for events (or hung up), timeout in ms, -1 to wait forever
+fn wait_fd(stream: &UnixStream, events: libc::c_short, timeout: i32) -> std::io::Result<()> {
+    let mut fd = libc::pollfd { fd: stream.as_raw_fd(), events, revents: 0 };
+    loop {
+        // SAFETY: fd is a single valid pollfd and the stream outlives the call
+        match unsafe { libc::poll(&mut fd, 1, timeout) } {
+            0 => return Err(std::io::ErrorKind::TimedOut.into()),
+            n if n > 0 => return Ok(()),
+            _ => {
+                let e = std::io::Error::last_os_error();
+                if e.kind() != std::io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct IconRequest {
+    icon: String,
+    size: Option<u16>,
+    theme: Option<String>
+}
+
+fn handle_request(line: &str) -> Option<String> {
+    let line = line.trim();
+    if line.is_empty() {
+        return None;
+    }
+    match serde_json::from_str::<IconRequest>(line) {
+        Ok(request) => {
+            let path = resolve_icon(&request.icon, request.size, request.theme.as_deref());
+            Some(serde_json::json!({
+                "resource": "icon",
+                "warning": if path.is_some() { 0.0 } else { 1.0 },
+                "icon": "",
+                "data": {
+                    "icon": request.icon,
+                    "size": request.size,
+                    "theme": request.theme,
+                    "path": path
+                }
+            }).to_string())
+        },
+        Err(e) => {
+            eprintln!("Invalid request {line:?}: {e}");
+            None
+        }
+    }
+}
+
 fn send (name: String, value: serde_json::Value, tx: Option<mpsc::Sender<String>>) -> bool {
     // println!("{} Sending {}", Local::now().format("%Y-%m-%d %H:%M:%S%.3f"), name);
     match tx {
@@ -201,6 +332,36 @@ macro_rules! stat_updater { // New version, standby-proof!
     };
 }
 
+// Forwards values pushed by event-driven listeners as soon as they arrive, without waiting for the main loop
+macro_rules! channel_forwarder {
+    ($stats:expr, $rx:expr, $field:ident, $comparator:expr, $tx:expr, $name:expr) => {
+        {
+            let stats = Arc::clone(&$stats);
+            let tx = $tx.clone();
+            let rx = $rx;
+            thread::spawn(move || {
+                for new_value in rx {
+                    if let Ok(mut data) = stats.lock() {
+                        let should_send = match &data.$field {
+                            Some(old) => $comparator(old, &new_value),
+                            None => true
+                        };
+
+                        if should_send {
+                            let json_val = serde_json::to_value(&new_value).unwrap_or_default();
+                            if !send($name.to_string(), json_val, tx.clone()) {
+                                eprintln!("Dispatcher terminato, chiudo thread di {}", $name);
+                                break;
+                            }
+                        }
+                        data.$field = Some(new_value);
+                    }
+                }
+            });
+        }
+    };
+}
+
 fn always_changed<T>(_: &T, _: &T) -> bool {
     true
 }
@@ -209,8 +370,23 @@ fn ram_changed (old: &RamStats, new: &RamStats) -> bool {
     old.mem_percent.abs_diff(new.mem_percent) >= 1 || old.swap_percent.abs_diff(new.swap_percent) >= 1
 }
 
+fn memory_detail_changed (old: &MemoryDetailStats, new: &MemoryDetailStats) -> bool {
+    let swap_used = |m: &MemoryDetailStats| m.swaps.iter().map(|s| s.used).sum::<u64>();
+    let zram_used = |m: &MemoryDetailStats| m.zram.iter().map(|z| z.mem_used_total).sum::<u64>();
+    let one_percent = (new.total / 100).max(1);
+    old.used_percent != new.used_percent ||
+    old.swaps.len() != new.swaps.len() ||
+    swap_used(old).abs_diff(swap_used(new)) >= one_percent ||
+    zram_used(old).abs_diff(zram_used(new)) >= one_percent ||
+    old.cached.abs_diff(new.cached) >= one_percent
+}
+
 fn brightness_changed (old: &EmbeddedDisplayStats, new: &EmbeddedDisplayStats) -> bool {
-    old.brightness_current != new.brightness_current
+    old.brightness_current != new.brightness_current || old.device != new.device
+}
+
+fn leds_changed (old: &LedStats, new: &LedStats) -> bool {
+    old.capslock != new.capslock || old.numlock != new.numlock || old.scrolllock != new.scrolllock || old.kbd_backlight != new.kbd_backlight
 }
 
 fn network_changed (old: &NetworkStats, new: &NetworkStats) -> bool {
@@ -221,6 +397,32 @@ fn disk_changed (old: &DiskStats, new: &DiskStats) -> bool {
     old.used_percent != new.used_percent
 }
 
+fn fans_changed (old: &FanStats, new: &FanStats) -> bool {
+    old.fans.len() != new.fans.len() ||
+    old.warn != new.warn ||
+    old.fans.iter().zip(new.fans.iter()).any(|(o, n)| o.rpm.abs_diff(n.rpm) >= 100 || o.pwm != n.pwm)
+}
+
+fn cpufreq_changed (old: &CpuFreqStats, new: &CpuFreqStats) -> bool {
+    old.throttling != new.throttling || old.governor != new.governor || old.avg_mhz.abs_diff(new.avg_mhz) >= 100
+}
+
+fn power_profile_changed (old: &PowerProfileStats, new: &PowerProfileStats) -> bool {
+    old.active != new.active || old.degraded != new.degraded || old.available != new.available
+}
+
+// The listener retries every 5 s without bluetoothd, the "off" state is sent once
+fn bluetooth_changed (old: &BluezStats, new: &BluezStats) -> bool {
+    old != new
+}
+
+fn pressure_changed (old: &PressureStats, new: &PressureStats) -> bool {
+    let avg10 = |p: &Option<PressureResource>| p.as_ref().map_or(0.0, |r| r.some.avg10);
+    (avg10(&old.cpu) - avg10(&new.cpu)).abs() >= 1.0 ||
+    (avg10(&old.memory) - avg10(&new.memory)).abs() >= 1.0 ||
+    (avg10(&old.io) - avg10(&new.io)).abs() >= 1.0
+}
+
 fn main() {
     let config = Config::init("~/.config/ratatoskr/config.json");
     log_to_file(format!("Loaded configuration: {:?}", config));
@@ -255,15 +457,41 @@ fn main() {
     // let msock = Arc::new(Mutex::new(UnixDatagram::unbound().expect("Error msock")));
 
     stat_updater!(stats, Duration::from_secs(1), get_ram_info, ram, false, ram_changed, &tx, "ram");
+    stat_updater!(stats, Duration::from_secs(2), get_memory_detail, memory_detail, false, memory_detail_changed, &tx, "memory_detail");
     stat_updater!(stats, Duration::from_secs(5), get_disk_info, disk, false, disk_changed, &tx, "disk");
     stat_updater!(stats, Duration::from_secs(1), get_sys_temperatures, temperature, false, always_changed, &tx, "temperature");
-    stat_updater!(stats, Duration::from_secs(600), get_weather, weather, true, always_changed, &tx, "weather");
+    stat_updater!(stats, Duration::from_secs(config.weather_interval), get_weather, weather, true, always_changed, &tx, "weather");
+    stat_updater!(stats, Duration::from_secs(60), get_sun_stats, sun, true, always_changed, &tx, "sun");
     stat_updater!(stats, Duration::from_millis(500), get_load_avg, loadavg, false, always_changed, &tx, "loadavg");
     // stat_updater!(stats, Duration::from_secs(1), get_volume, volume, false, &tx, "volume");
     stat_updater!(stats, Duration::from_secs(1), get_battery, battery, false, always_changed, &tx, "battery");
+    stat_updater!(stats, Duration::from_secs(2), get_power_profile, power_profile, false, power_profile_changed, &tx, "power_profile");
     stat_updater!(stats, Duration::from_secs(1), get_network_stats, network, false, network_changed, &tx, "network");
-    stat_updater!(stats, Duration::from_secs(1), get_brightness_stats, display, false, brightness_changed, &tx, "display");
+    stat_updater!(stats, Duration::from_millis(250), get_led_stats, leds, false, leds_changed, &tx, "leds");
+    stat_updater!(stats, Duration::from_secs(2), get_fan_stats, fans, false, fans_changed, &tx, "fans");
+    stat_updater!(stats, Duration::from_secs(1), get_cpufreq_stats, cpufreq, false, cpufreq_changed, &tx, "cpufreq");
+    stat_updater!(stats, Duration::from_secs(2), get_pressure_stats, pressure, false, pressure_changed, &tx, "pressure");
+
+
+    let (tx_display, rx_display) = std::sync::mpsc::channel();
+    spawn_backlight_listener(tx_display);
+    channel_forwarder!(stats, rx_display, display, brightness_changed, &tx, "display");
+
+    let (tx_media, rx_media) = std::sync::mpsc::channel();
+    spawn_mpris_listener(tx_media);
+    channel_forwarder!(stats, rx_media, media, always_changed, &tx, "media");
+
+    let (tx_workspaces, rx_workspaces) = std::sync::mpsc::channel();
+    let (tx_windows, rx_windows) = std::sync::mpsc::channel();
+    let (tx_focused_window, rx_focused_window) = std::sync::mpsc::channel();
+    spawn_niri_listener(tx_workspaces, tx_windows, tx_focused_window);
+    channel_forwarder!(stats, rx_workspaces, workspaces, always_changed, &tx, "workspaces");
+    channel_forwarder!(stats, rx_windows, windows, always_changed, &tx, "windows");
+    channel_forwarder!(stats, rx_focused_window, focused_window, always_changed, &tx, "focused_window");
 
+    let (tx_bluez, rx_bluez) = std::sync::mpsc::channel();
+    spawn_bluez_listener(tx_bluez);
+    channel_forwarder!(stats, rx_bluez, bluetooth, bluetooth_changed, &tx, "bluetooth");
 
     let (tx_audio, rx_audio) = std::sync::mpsc::channel();
     spawn_volume_listener(tx_audio);
@@ -279,14 +507,28 @@ fn main() {
 
             // send_burst(&data, tx.clone().unwrap());
         }
-        while let Ok(volume_obj) = rx_audio.try_recv() {
+        while let Ok(audio_update) = rx_audio.try_recv() {
             if let Ok(mut data) = stats.lock() {
-                let json_val = serde_json::to_value(&volume_obj).unwrap_or_default();
-                if !send("volume".to_string(), json_val, tx.clone()) {
-                    // eprintln!("Dispatcher terminato, chiudo thread di {}", $name);
-                    // break;
+                match audio_update {
+                    AudioUpdate::Volume(volume_obj) => {
+                        let json_val = serde_json::to_value(&volume_obj).unwrap_or_default();
+                        if !send("volume".to_string(), json_val, tx.clone()) {
+                            // eprintln!("Dispatcher terminato, chiudo thread di {}", $name);
+                            // break;
+                        }
+                        data.volume = Some(volume_obj);
+                    },
+                    AudioUpdate::Microphone(microphone_obj) => {
+                        let json_val = serde_json::to_value(&microphone_obj).unwrap_or_default();
+                        let _ = send("microphone".to_string(), json_val, tx.clone());
+                        data.microphone = Some(microphone_obj);
+                    },
+                    AudioUpdate::Streams(streams_obj) => {
+                        let json_val = serde_json::to_value(&streams_obj).unwrap_or_default();
+                        let _ = send("audio_streams".to_string(), json_val, tx.clone());
+                        data.audio_streams = Some(streams_obj);
+                    }
                 }
-                data.volume = Some(volume_obj);
             }
         }
 
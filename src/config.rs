@@ -1,7 +1,8 @@
-use std::{fs, ops::Range};
+use std::{collections::HashMap, fs, ops::Range};
 use serde::Deserialize;
 use once_cell::sync::OnceCell;
 
+use crate::UPowerDeviceKind;
 use crate::utils::{DEFAULT_WHITE, hsv_to_rgb};
 
 static CONFIG: OnceCell<Config> = OnceCell::new();
@@ -12,10 +13,19 @@ const DEFAULT_DISK_RANGE: [f64; 2] = [60.0, 90.0];
 const DEFAULT_TEMPERATURE_RANGE: [f64; 2] = [80.0, 99.0];
 const DEFAULT_AVG_LOAD_RANGE: [f64; 2] = [0.0, 1.0];
 const DEFAULT_BATTERY_RANGE: [f64; 2] = [20.0, 70.0];
+const DEFAULT_BATTERY_HEALTH_RANGE: [f64; 2] = [60.0, 80.0];
 const DEFAULT_WLAN_SIGNAL_RANGE: [f64; 2] = [20.0, 60.0];
 const DEFAULT_VOLUME_HEADPHONES_RANGE: [f64; 2] = [20.0, 90.0];
 const DEFAULT_VOLUME_SPEAKERS_RANGE: [f64; 2] = [20.0, 90.0];
+const DEFAULT_VOLUME_MICROPHONE_RANGE: [f64; 2] = [80.0, 100.0];
 const DEFAULT_BLUETOOTH_BATTERY_RANGE: [f64; 2] = [10.0, 35.0];
+const DEFAULT_BATTERY_ETA_WINDOW: u64 = 300;
+const DEFAULT_WEATHER_INTERVAL: u64 = 600;
+const DEFAULT_WEATHER_URL: &str = "https://api.open-meteo.com/v1/forecast";
+const DEFAULT_FAN_RANGE: [f64; 2] = [85.0, 100.0];
+const DEFAULT_PRESSURE_CPU_RANGE: [f64; 2] = [20.0, 60.0];
+const DEFAULT_PRESSURE_MEMORY_RANGE: [f64; 2] = [5.0, 30.0];
+const DEFAULT_PRESSURE_IO_RANGE: [f64; 2] = [10.0, 50.0];
 
 #[derive(Debug, Clone)]
 pub struct Threshold {
@@ -23,6 +33,25 @@ pub struct Threshold {
     pub high_is_better: bool
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WeatherUnits {
+    Metric, // °C, km/h, mm
+    Imperial // °F, mph, inch
+}
+
+/// A weather warning contribution. All the conditions set must match, ranges give
+/// a factor between 0.0 and 1.0 (a reversed range, e.g. [10, 0], grows as the value decreases)
+#[derive(Debug, Clone, Deserialize)]
+pub struct WeatherWarnRule {
+    #[serde(default)]
+    pub codes: Vec<u8>, // WMO codes
+    pub text: Option<String>,
+    pub temp: Option<[f64; 2]>,
+    pub humidity: Option<[f64; 2]>,
+    pub wind: Option<[f64; 2]>,
+    pub weight: f64
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub threshold_ram: Threshold,
@@ -31,10 +60,30 @@ pub struct Config {
     pub threshold_temperature: Threshold,
     pub threshold_avg_load: Threshold,
     pub threshold_battery: Threshold,
+    pub threshold_battery_health: Threshold,
     pub threshold_wlan_signal: Threshold,
     pub threshold_volume_headphones: Threshold,
     pub threshold_volume_speakers: Threshold,
+    pub threshold_volume_microphone: Threshold,
     pub threshold_bluetooth_battery: Threshold,
+    pub threshold_bluetooth_battery_kinds: HashMap<String, Threshold>,
+    pub bluetooth_warn_device: Option<String>,
+    pub bluetooth_icons: HashMap<String, String>,
+    pub bluetooth_aliases: HashMap<String, String>,
+    pub threshold_fan: Threshold,
+    pub threshold_pressure_cpu: Threshold,
+    pub threshold_pressure_memory: Threshold,
+    pub threshold_pressure_io: Threshold,
+    pub loadavg_use_pressure: bool,
+    pub backlight_device: Option<String>,
+    pub battery_eta_window: u64,
+    pub weather_latitude: Option<f64>,
+    pub weather_longitude: Option<f64>,
+    pub weather_locality: Option<String>,
+    pub weather_units: WeatherUnits,
+    pub weather_interval: u64,
+    pub weather_url: String,
+    pub weather_warn_rules: Vec<WeatherWarnRule>,
     pub write_json: bool
 }
 
@@ -46,10 +95,30 @@ struct RawConfig {
     threshold_temperature: Option<serde_json::Value>,
     threshold_avg_load: Option<serde_json::Value>,
     threshold_battery: Option<serde_json::Value>,
+    threshold_battery_health: Option<serde_json::Value>,
     threshold_wlan_signal: Option<serde_json::Value>,
     threshold_volume_headphones: Option<serde_json::Value>,
     threshold_volume_speakers: Option<serde_json::Value>,
+    threshold_volume_microphone: Option<serde_json::Value>,
     threshold_bluetooth_battery: Option<serde_json::Value>,
+    threshold_bluetooth_battery_kinds: Option<HashMap<String, serde_json::Value>>,
+    bluetooth_warn_device: Option<String>,
+    bluetooth_icons: Option<HashMap<String, String>>,
+    bluetooth_aliases: Option<HashMap<String, String>>,
+    threshold_fan: Option<serde_json::Value>,
+    threshold_pressure_cpu: Option<serde_json::Value>,
+    threshold_pressure_memory: Option<serde_json::Value>,
+    threshold_pressure_io: Option<serde_json::Value>,
+    loadavg_use_pressure: Option<bool>,
+    backlight_device: Option<String>,
+    battery_eta_window: Option<u64>,
+    weather_latitude: Option<f64>,
+    weather_longitude: Option<f64>,
+    weather_locality: Option<String>,
+    weather_units: Option<String>,
+    weather_interval: Option<u64>,
+    weather_url: Option<String>,
+    weather_warn_rules: Option<serde_json::Value>,
     write_json: Option<bool>
 }
 
@@ -129,6 +198,54 @@ impl Threshold {
     }
 }
 
+impl WeatherWarnRule {
+    fn rule(codes: &[u8], temp: Option<[f64; 2]>, weight: f64) -> Self {
+        WeatherWarnRule { codes: codes.to_vec(), text: None, temp, humidity: None, wind: None, weight }
+    }
+
+    /// weight * match factor, 0.0 if a condition does not match
+    pub fn get_warn_level(&self, code: u8, text: &str, temp: f64, humidity: f64, wind: f64) -> f64 {
+        if !self.codes.is_empty() && !self.codes.contains(&code) {
+            return 0.0;
+        }
+        if self.text.as_ref().is_some_and(|t| !t.eq_ignore_ascii_case(text)) {
+            return 0.0;
+        }
+        let factor = [(self.temp, temp), (self.humidity, humidity), (self.wind, wind)]
+            .into_iter()
+            .filter_map(|(range, value)| range.map(|r| range_factor(r, value)))
+            .product::<f64>();
+        self.weight * factor
+    }
+}
+
+fn range_factor([start, end]: [f64; 2], value: f64) -> f64 {
+    if (end - start).abs() < f64::EPSILON {
+        return if value >= start { 1.0 } else { 0.0 };
+    }
+    ((value - start) / (end - start)).clamp(0.0, 1.0)
+}
+
+/// Hot and cold days, then fog, rain and storms
+fn default_weather_warn_rules(units: WeatherUnits) -> Vec<WeatherWarnRule> {
+    // Above 28 °C the warning jumps to about 0.8 and reaches 1.0 at 36 °C
+    let (hot_from, hot, cold) = match units {
+        WeatherUnits::Metric => (29.0, 36.0, [10.0, 0.0]),
+        WeatherUnits::Imperial => (83.0, 97.0, [50.0, 32.0])
+    };
+    vec![
+        WeatherWarnRule::rule(&[], Some([hot_from, hot_from]), 0.8),
+        WeatherWarnRule::rule(&[], Some([hot_from, hot]), 0.2),
+        WeatherWarnRule::rule(&[], Some(cold), 1.0),
+        WeatherWarnRule::rule(&[3], None, 0.2),
+        WeatherWarnRule::rule(&[45, 48], None, 0.3),
+        WeatherWarnRule::rule(&[61], None, 0.2),
+        WeatherWarnRule::rule(&[63], None, 0.4),
+        WeatherWarnRule::rule(&[65], None, 0.6),
+        WeatherWarnRule::rule(&[95, 96, 99], None, 0.8)
+    ]
+}
+
 impl Config {
     pub fn init(path: &str) -> &'static Config {
         CONFIG.get_or_init(|| Config::load_from_file(path))
@@ -138,6 +255,14 @@ impl Config {
         CONFIG.get().expect("Config not initialized")
     }
 
+    /// Threshold for a peripheral battery: the one configured for its kind
+    /// (e.g. "Mouse", "Headset") if any, otherwise threshold_bluetooth_battery
+    pub fn bluetooth_battery_threshold(&self, kind: &UPowerDeviceKind) -> &Threshold {
+        self.threshold_bluetooth_battery_kinds
+            .get(&format!("{:?}", kind))
+            .unwrap_or(&self.threshold_bluetooth_battery)
+    }
+
     pub fn load_from_file(path: &str) -> Self {
         let expanded_path = shellexpand::tilde(path);
         
@@ -148,6 +273,15 @@ impl Config {
                 RawConfig::default()
             });
 
+        let weather_units = match raw.weather_units.as_deref() {
+            None | Some("metric") => WeatherUnits::Metric,
+            Some("imperial") => WeatherUnits::Imperial,
+            Some(other) => {
+                eprintln!("Config Warning: invalid weather_units {:?}, uso il default.", other);
+                WeatherUnits::Metric
+            }
+        };
+
         Config {
             threshold_ram: Threshold::from_json_with_default(raw.threshold_ram, Some(DEFAULT_RAM_RANGE), false),
             threshold_swap: Threshold::from_json_with_default(raw.threshold_swap, Some(DEFAULT_SWAP_RANGE), false),
@@ -155,12 +289,119 @@ impl Config {
             threshold_temperature: Threshold::from_json_with_default(raw.threshold_temperature, Some(DEFAULT_TEMPERATURE_RANGE), false),
             threshold_avg_load: Threshold::from_json_with_default(raw.threshold_avg_load, Some(DEFAULT_AVG_LOAD_RANGE), false),
             threshold_battery: Threshold::from_json_with_default(raw.threshold_battery, Some(DEFAULT_BATTERY_RANGE), true),
+            threshold_battery_health: Threshold::from_json_with_default(raw.threshold_battery_health, Some(DEFAULT_BATTERY_HEALTH_RANGE), true),
             threshold_wlan_signal: Threshold::from_json_with_default(raw.threshold_wlan_signal, Some(DEFAULT_WLAN_SIGNAL_RANGE), true),
             threshold_volume_headphones: Threshold::from_json_with_default(raw.threshold_volume_headphones, Some(DEFAULT_VOLUME_HEADPHONES_RANGE), false),
             threshold_volume_speakers: Threshold::from_json_with_default(raw.threshold_volume_speakers, Some(DEFAULT_VOLUME_SPEAKERS_RANGE), false),
+            threshold_volume_microphone: Threshold::from_json_with_default(raw.threshold_volume_microphone, Some(DEFAULT_VOLUME_MICROPHONE_RANGE), false),
             threshold_bluetooth_battery: Threshold::from_json_with_default(raw.threshold_bluetooth_battery, Some(DEFAULT_BLUETOOTH_BATTERY_RANGE), true),
+            threshold_bluetooth_battery_kinds: raw.threshold_bluetooth_battery_kinds
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(kind, value)| (kind, Threshold::from_json_with_default(Some(value), Some(DEFAULT_BLUETOOTH_BATTERY_RANGE), true)))
+                .collect(),
+            bluetooth_warn_device: raw.bluetooth_warn_device,
+            bluetooth_icons: raw.bluetooth_icons.unwrap_or_default(),
+            bluetooth_aliases: raw.bluetooth_aliases.unwrap_or_default(),
+            threshold_fan: Threshold::from_json_with_default(raw.threshold_fan, Some(DEFAULT_FAN_RANGE), false),
+            threshold_pressure_cpu: Threshold::from_json_with_default(raw.threshold_pressure_cpu, Some(DEFAULT_PRESSURE_CPU_RANGE), false),
+            threshold_pressure_memory: Threshold::from_json_with_default(raw.threshold_pressure_memory, Some(DEFAULT_PRESSURE_MEMORY_RANGE), false),
+            threshold_pressure_io: Threshold::from_json_with_default(raw.threshold_pressure_io, Some(DEFAULT_PRESSURE_IO_RANGE), false),
+            loadavg_use_pressure: raw.loadavg_use_pressure.unwrap_or(false),
+            backlight_device: raw.backlight_device,
+            battery_eta_window: raw.battery_eta_window.unwrap_or(DEFAULT_BATTERY_ETA_WINDOW),
+            weather_latitude: raw.weather_latitude,
+            weather_longitude: raw.weather_longitude,
+            weather_locality: raw.weather_locality,
+            weather_units,
+            // Open-Meteo updates its models every 15 minutes, less than one minute makes no sense
+            weather_interval: raw.weather_interval.unwrap_or(DEFAULT_WEATHER_INTERVAL).max(60),
+            weather_url: raw.weather_url.unwrap_or_else(|| DEFAULT_WEATHER_URL.to_string()),
+            weather_warn_rules: match raw.weather_warn_rules {
+                Some(value) => serde_json::from_value(value).unwrap_or_else(|e| {
+                    eprintln!("Config Warning: invalid weather_warn_rules ({}), uso il default.", e);
+                    default_weather_warn_rules(weather_units)
+                }),
+                None => default_weather_warn_rules(weather_units)
+            },
             write_json: raw.write_json.unwrap_or(false)
 
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod weather_warn_tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn range_factor_increasing() {
+        assert_eq!(range_factor([28.0, 36.0], 20.0), 0.0);
+        assert_eq!(range_factor([28.0, 36.0], 28.0), 0.0);
+        assert!(close(range_factor([28.0, 36.0], 29.0), 0.125));
+        assert!(close(range_factor([28.0, 36.0], 32.0), 0.5));
+        assert_eq!(range_factor([28.0, 36.0], 36.0), 1.0);
+        assert_eq!(range_factor([28.0, 36.0], 40.0), 1.0);
+    }
+
+    #[test]
+    fn range_factor_reversed() {
+        assert_eq!(range_factor([10.0, 0.0], 15.0), 0.0);
+        assert_eq!(range_factor([10.0, 0.0], 10.0), 0.0);
+        assert!(close(range_factor([10.0, 0.0], 7.0), 0.3));
+        assert_eq!(range_factor([10.0, 0.0], 0.0), 1.0);
+        assert_eq!(range_factor([10.0, 0.0], -5.0), 1.0);
+    }
+
+    #[test]
+    fn range_factor_single_value() {
+        assert_eq!(range_factor([30.0, 30.0], 29.0), 0.0);
+        assert_eq!(range_factor([30.0, 30.0], 30.0), 1.0);
+    }
+
+    #[test]
+    fn rule_matches_codes_and_text() {
+        let fog = WeatherWarnRule::rule(&[45, 48], None, 0.3);
+        assert_eq!(fog.get_warn_level(45, "Fog", 15.0, 90.0, 0.0), 0.3);
+        assert_eq!(fog.get_warn_level(3, "Overcast", 15.0, 90.0, 0.0), 0.0);
+
+        let text = WeatherWarnRule { text: Some("Fog".to_string()), ..WeatherWarnRule::rule(&[], None, 0.5) };
+        assert_eq!(text.get_warn_level(45, "fog", 15.0, 90.0, 0.0), 0.5);
+        assert_eq!(text.get_warn_level(45, "Nebbia", 15.0, 90.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn rule_multiplies_ranges() {
+        let windy_snow = WeatherWarnRule { wind: Some([20.0, 50.0]), ..WeatherWarnRule::rule(&[71, 73, 75], Some([2.0, -8.0]), 1.0) };
+        // wind factor 0.5, temp factor 0.5
+        assert!(close(windy_snow.get_warn_level(73, "Snow", -3.0, 80.0, 35.0), 0.25));
+        assert_eq!(windy_snow.get_warn_level(73, "Snow", -3.0, 80.0, 10.0), 0.0);
+        assert_eq!(windy_snow.get_warn_level(61, "Rain", -3.0, 80.0, 35.0), 0.0);
+    }
+
+    #[test]
+    fn default_rules() {
+        let rules = default_weather_warn_rules(WeatherUnits::Metric);
+        let level = |code: u8, temp: f64| rules.iter().map(|rule| rule.get_warn_level(code, "", temp, 50.0, 0.0)).sum::<f64>();
+        assert_eq!(level(0, 20.0), 0.0);
+        assert_eq!(level(0, 28.0), 0.0);
+        assert!(close(level(0, 29.0), 0.8));
+        // The old curve was temp / 36
+        assert!((level(0, 32.0) - 32.0 / 36.0).abs() < 0.01);
+        assert!(close(level(0, 36.0), 1.0));
+        assert!(close(level(0, 5.0), 0.5));
+        assert!(close(level(63, 20.0), 0.4));
+        assert!(close(level(95, 5.0), 1.3));
+
+        let imperial = default_weather_warn_rules(WeatherUnits::Imperial);
+        let level = |temp: f64| imperial.iter().map(|rule| rule.get_warn_level(0, "", temp, 50.0, 0.0)).sum::<f64>();
+        assert!(close(level(41.0), 0.5));
+        assert_eq!(level(82.0), 0.0);
+        assert!(close(level(83.0), 0.8));
+        assert!(close(level(97.0), 1.0));
+    }
+}
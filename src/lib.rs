@@ -16,6 +16,20 @@ pub struct SystemStats {
     pub network: Option<NetworkStats>,
     pub display: Option<EmbeddedDisplayStats>,
     pub bluetooth_batteries: Option<BluetoothStats>,
+    pub bluetooth: Option<BluezStats>,
+    pub fans: Option<FanStats>,
+    pub cpufreq: Option<CpuFreqStats>,
+    pub pressure: Option<PressureStats>,
+    pub memory_detail: Option<MemoryDetailStats>,
+    pub leds: Option<LedStats>,
+    pub microphone: Option<MicrophoneStats>,
+    pub audio_streams: Option<AudioStreamsStats>,
+    pub media: Option<MediaStats>,
+    pub power_profile: Option<PowerProfileStats>,
+    pub sun: Option<SunStats>,
+    pub workspaces: Option<WorkspacesStats>,
+    pub windows: Option<WindowsStats>,
+    pub focused_window: Option<FocusedWindowStats>,
     pub written_at: u64,
     pub metronome: bool
 }
@@ -35,6 +49,45 @@ pub struct RamStats {
     pub warn: f64
 }
 
+// All sizes are in bytes
+#[derive(Default, Serialize)]
+pub struct MemoryDetailStats {
+    pub total: u64,
+    pub free: u64,
+    pub available: u64,
+    pub used: u64, // total - available
+    pub cached: u64,
+    pub buffers: u64,
+    pub shared: u64,
+    pub dirty: u64,
+    pub writeback: u64,
+    pub used_percent: u64,
+    pub swaps: Vec<SwapDevice>,
+    pub zram: Vec<ZramDevice>,
+    pub icon: String,
+    pub warn: f64
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SwapDevice {
+    pub name: String,
+    pub kind: String,
+    pub size: u64,
+    pub used: u64,
+    pub priority: i32,
+    pub is_zram: bool
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ZramDevice {
+    pub name: String,
+    pub disksize: u64,
+    pub orig_data_size: u64,
+    pub compr_data_size: u64,
+    pub mem_used_total: u64, // RAM really taken by the device, allocator overhead included
+    pub compression_ratio: Option<f64>
+}
+
 #[derive(Default, Serialize)]
 pub struct DiskStats {
     pub total_size: u64,
@@ -53,10 +106,11 @@ pub struct TempStats {
     pub warn: f64
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct WeatherStats {
     pub icon: String,
     pub icon_name: String,
+    pub code: u8, // WMO weather interpretation code
     pub temp: i8,
     pub temp_real: i8,
     pub temp_unit: String,
@@ -69,10 +123,60 @@ pub struct WeatherStats {
     pub daylight: f64,
     pub locality: String,
     pub humidity: u8,
+    pub wind_speed: f64,
+    pub wind_unit: String,
     pub updated: Option<String>,
+    pub stale: bool, // last good value, the latest request failed
+    pub hourly: Vec<WeatherHour>, // next 24 hours, starting from the current one
+    pub daily: Vec<WeatherDay>, // next 7 days, starting from today
     pub warn: Option<f64>
 }
 
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct WeatherHour {
+    pub time: String, // local time, e.g. "2026-10-19T15:00"
+    pub temp: f64,
+    pub precipitation_probability: Option<u8>,
+    pub code: u8,
+    pub icon: String,
+    pub text: String,
+    pub wind_speed: f64
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct WeatherDay {
+    pub date: String, // e.g. "2026-10-19"
+    pub day: String, // e.g. "Monday"
+    pub temp_min: f64,
+    pub temp_max: f64,
+    pub code: u8,
+    pub icon: String,
+    pub text: String,
+    pub sunrise: String,
+    pub sunset: String
+}
+
+#[derive(Default, Serialize)]
+pub struct SunStats {
+    // Local times ("HH:MM"), None when the event does not happen today (polar day or night)
+    pub sunrise: Option<String>,
+    pub sunset: Option<String>,
+    pub solar_noon: String,
+    pub civil_dawn: Option<String>,
+    pub civil_dusk: Option<String>,
+    pub nautical_dawn: Option<String>,
+    pub nautical_dusk: Option<String>,
+    pub sunrise_mins: Option<u64>, // minutes since local midnight
+    pub sunset_mins: Option<u64>,
+    pub day_length: u64, // minutes
+    pub daylight: f64, // fraction of today's daylight already elapsed, 0.0 before sunrise and 1.0 after sunset
+    pub elevation: f64, // degrees above the horizon
+    pub is_day: bool,
+    pub phase: String, // day, civil_twilight, nautical_twilight, astronomical_twilight or night
+    pub icon: String,
+    pub warn: f64
+}
+
 #[derive(Default, Serialize)]
 pub struct AvgLoadStats {
     pub m1: f64,
@@ -85,13 +189,122 @@ pub struct AvgLoadStats {
 
 #[derive(Default, Serialize)]
 pub struct VolumeStats {
-    pub value: i64,
-    pub icon: String, // legacy
-    pub color: String, // legacy
-    pub clazz: String, // legacy
+    pub value: i64, // 0 when muted
+    pub muted: bool,
+    pub sink: String,
+    pub description: String,
+    pub port: Option<String>,
+    pub port_description: Option<String>,
+    pub port_type: String, // speaker, headphones, headset, hdmi, bluetooth, usb or unknown
+    pub bt_profile: Option<String>,
+    pub bt_codec: Option<String>,
+    pub balance: f32, // -1.0 (left) .. 1.0 (right)
+    pub icon: String,
+    pub color: String,
+    pub clazz: String, // legacy, same as port_type
     pub headphones: i8,
     pub warn: f64
 }
+#[derive(Default, Serialize)]
+pub struct MicrophoneStats {
+    pub name: String,
+    pub description: String,
+    pub value: i64,
+    pub muted: bool,
+    pub in_use: bool, // some application is recording from a (non monitor) source
+    pub recording_apps: Vec<String>,
+    pub icon: String,
+    pub warn: f64
+}
+
+#[derive(Default, Serialize)]
+pub struct AudioStreamsStats {
+    pub streams: Vec<AudioStream>,
+    pub icon: String,
+    pub warn: f64
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AudioStream {
+    pub index: u32,
+    pub application: String,
+    pub icon_name: Option<String>,
+    pub media_name: Option<String>,
+    pub sink: u32,
+    pub volume: i64,
+    pub muted: bool,
+    pub corked: bool // paused
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct NiriWorkspace {
+    pub id: u64,
+    pub idx: u8, // index on its output, as shown by niri
+    pub name: Option<String>,
+    pub output: Option<String>,
+    pub is_active: bool,
+    pub is_focused: bool,
+    pub is_urgent: bool,
+    pub active_window_id: Option<u64>
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct NiriWindow {
+    pub id: u64,
+    pub title: Option<String>,
+    pub app_id: Option<String>,
+    pub pid: Option<i32>,
+    pub workspace_id: Option<u64>,
+    pub is_focused: bool,
+    pub is_floating: bool,
+    pub is_urgent: bool
+}
+
+#[derive(Debug, Default, Clone, Serialize, PartialEq)]
+pub struct WorkspacesStats {
+    pub workspaces: Vec<NiriWorkspace>, // sorted by output and index
+    pub focused: Option<u64>,
+    pub icon: String,
+    pub warn: f64 // 1.0 if a workspace is urgent
+}
+
+#[derive(Debug, Default, Clone, Serialize, PartialEq)]
+pub struct WindowsStats {
+    pub windows: Vec<NiriWindow>, // sorted by workspace and id
+    pub icon: String,
+    pub warn: f64 // 1.0 if a window is urgent
+}
+
+#[derive(Debug, Default, Clone, Serialize, PartialEq)]
+pub struct FocusedWindowStats {
+    pub window: Option<NiriWindow>,
+    pub icon: String,
+    pub warn: f64
+}
+
+// Messages sent by the audio listener, one for each resource it tracks
+pub enum AudioUpdate {
+    Volume(VolumeStats),
+    Microphone(MicrophoneStats),
+    Streams(AudioStreamsStats)
+}
+
+#[derive(Default, Serialize)]
+pub struct MediaStats {
+    pub player: Option<String>, // e.g. "spotify" for org.mpris.MediaPlayer2.spotify
+    pub players: Vec<String>,
+    pub status: String, // Playing, Paused or Stopped
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub art_url: Option<String>,
+    pub position: Option<f64>, // seconds, sampled at `updated`
+    pub length: Option<f64>, // seconds
+    pub updated: u64,
+    pub icon: String,
+    pub warn: f64
+}
+
 #[derive(Deserialize)]
 pub struct VolumeObj {
     pub value: i64,
@@ -100,20 +313,60 @@ pub struct VolumeObj {
     pub headphones: i8
 }
 
+// Aggregated over all system batteries, see `batteries` for the single packs
 #[derive(Default, Serialize)]
 pub struct BatteryStats {
     pub percentage: i32,
+    pub energy: f32,
     pub capacity: f32,
     pub capacity_design: f32,
     pub cycles: Option<u32>,
-    pub eta: Option<f32>,
+    pub eta: Option<f32>, // minutes, from firmware
+    pub eta_smoothed: Option<f32>, // minutes
+    pub eta_smoothed_source: String, // history, firmware or none
+    pub watt_smoothed: Option<f32>,
     pub state: String,
+    pub ac_online: Option<bool>,
+    pub health: Option<f32>, // capacity / capacity_design, in percent
+    pub health_warn: f64,
+    pub batteries: Vec<BatteryUnit>,
     pub icon: String,
     pub color: Option<String>,
     pub watt: f32,
     pub warn: f64
 }
 
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BatteryUnit {
+    pub name: Option<String>, // sysfs name, e.g. BAT0
+    pub vendor: Option<String>,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub technology: String,
+    pub health: Option<f32>,
+    pub health_warn: f64,
+    pub charge_start_threshold: Option<u8>,
+    pub charge_end_threshold: Option<u8>,
+    pub percentage: i32,
+    pub energy: f32,
+    pub capacity: f32,
+    pub capacity_design: f32,
+    pub cycles: Option<u32>,
+    pub eta: Option<f32>,
+    pub state: String,
+    pub watt: f32
+}
+
+#[derive(Default, Serialize)]
+pub struct PowerProfileStats {
+    pub active: String,
+    pub available: Vec<String>,
+    pub degraded: Option<String>, // reason reported by power-profiles-daemon
+    pub source: String, // power-profiles-daemon or platform_profile
+    pub icon: String,
+    pub warn: f64
+}
+
 #[derive(Default,Serialize,Debug)]
 pub struct NetworkStats {
     pub iface: String,
@@ -128,22 +381,123 @@ pub struct NetworkStats {
 
 #[derive(Default, Serialize)]
 pub struct EmbeddedDisplayStats {
+    pub device: String,
     pub brightness_current: u32,
     pub brightness_max: u32,
     pub perc: u8,
+    pub devices: Vec<BacklightDevice>,
     pub icon: String,
     pub warn: f64
 }
 
 #[derive(Debug, Clone, Serialize)]
+pub struct BacklightDevice {
+    pub name: String,
+    pub kind: String, // firmware, platform or raw
+    pub brightness_current: u32,
+    pub brightness_max: u32,
+    pub perc: u8
+}
+
+#[derive(Default, Serialize)]
+pub struct FanStats {
+    pub fans: Vec<FanInfo>,
+    pub max_temp: Option<f64>,
+    pub icon: String,
+    pub warn: f64
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FanInfo {
+    pub chip: String,
+    pub label: String,
+    pub rpm: u32,
+    pub rpm_max: Option<u32>,
+    pub pwm: Option<u8>, // percent of the pwm duty cycle, only for chips where pwmN drives fanN
+    pub stopped: bool,
+    pub at_max: bool,
+    pub warn: f64
+}
+
+#[derive(Default, Serialize)]
+pub struct CpuFreqStats {
+    pub cores: Vec<CpuCoreFreq>,
+    pub avg_mhz: u32,
+    pub max_mhz: u32,
+    pub governor: Option<String>,
+    pub throttle_core_count: Option<u64>,
+    pub throttle_package_count: Option<u64>,
+    pub throttling: bool,
+    pub icon: String,
+    pub warn: f64
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CpuCoreFreq {
+    pub cpu: u32,
+    pub cur_mhz: u32,
+    pub min_mhz: u32,
+    pub max_mhz: u32,
+    pub governor: Option<String>
+}
+
+#[derive(Default, Serialize)]
+pub struct PressureStats {
+    pub cpu: Option<PressureResource>,
+    pub memory: Option<PressureResource>,
+    pub io: Option<PressureResource>,
+    pub cpu_warn: f64,
+    pub memory_warn: f64,
+    pub io_warn: f64,
+    pub icon: String,
+    pub warn: f64
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PressureResource {
+    pub some: PressureLine,
+    pub full: Option<PressureLine> // Not reported for cpu on older kernels
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PressureLine {
+    pub avg10: f64,
+    pub avg60: f64,
+    pub avg300: f64,
+    pub total: u64
+}
+
+#[derive(Default, Serialize)]
+pub struct LedStats {
+    pub kbd_backlight: Option<KbdBacklight>,
+    pub capslock: bool,
+    pub numlock: bool,
+    pub scrolllock: bool,
+    pub icon: String,
+    pub warn: f64
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KbdBacklight {
+    pub device: String,
+    pub brightness_current: u32,
+    pub brightness_max: u32,
+    pub perc: u8
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
 pub struct BatteryDevice {
     pub name: String,
+    pub model: String,
+    pub native_path: String,
+    pub serial: String,
     pub kind: UPowerDeviceKind,
+    pub icon: String,
     pub percentage: f64,
     pub warn: f64
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, PartialEq)]
 pub enum UPowerDeviceKind {
     Unknown,
     LinePower,
@@ -213,7 +567,30 @@ impl From<u32> for UPowerDeviceKind {
     }
 }
 
-#[derive(Default, Serialize)]
+#[derive(Debug, Default, Clone, Serialize, PartialEq)]
+pub struct BluezStats {
+    pub adapter: Option<String>, // e.g. "hci0"
+    pub address: Option<String>,
+    pub powered: bool,
+    pub discoverable: bool,
+    pub devices: Vec<BluezDevice>, // connected devices only
+    pub icon: String,
+    pub warn: f64
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BluezDevice {
+    pub address: String,
+    pub name: String, // Alias, which defaults to Name
+    pub icon: Option<String>, // freedesktop icon name from BlueZ, e.g. "audio-headset"
+    pub class: Option<u32>,
+    pub rssi: Option<i16>,
+    pub battery: Option<u8>, // None if the device does not report it
+    pub connected: bool,
+    pub warn: f64
+}
+
+#[derive(Default, Clone, Serialize, PartialEq)]
 pub struct BluetoothStats {
     pub devices: Vec<BatteryDevice>,
     pub icon: String,
@@ -1,11 +1,9 @@
 use std::{process::Command};
 
 use sysinfo::{Disks, System};
-use chrono::Utc;
-
-use crate::{AvgLoadStats, BatteryDevice, BatteryStats, BluetoothStats, UPowerDeviceKind, DiskStats, EmbeddedDisplayStats, NetworkStats, RamStats, TempStats, VolumeObj, VolumeStats, WeatherStats, config::Config, utils};
-
+use chrono::{Timelike, Utc};
 
+use crate::{AudioStream, AudioStreamsStats, AudioUpdate, AvgLoadStats, BacklightDevice, BatteryDevice, BatteryStats, BatteryUnit, BluetoothStats, BluezDevice, BluezStats, UPowerDeviceKind, CpuCoreFreq, CpuFreqStats, DiskStats, EmbeddedDisplayStats, FanInfo, FocusedWindowStats, NiriWindow, NiriWorkspace, WindowsStats, WorkspacesStats, FanStats, KbdBacklight, LedStats, MediaStats, MemoryDetailStats, MicrophoneStats, NetworkStats, PowerProfileStats, PressureLine, PressureResource, PressureStats, RamStats, SunStats, SwapDevice, TempStats, VolumeObj, VolumeStats, WeatherDay, WeatherHour, WeatherStats, ZramDevice, config::{Config, WeatherUnits}, utils};
 
 pub fn get_ram_info () -> Option<RamStats> {
     let mut sys = System::new();
@@ -60,6 +58,94 @@ pub fn get_ram_info () -> Option<RamStats> {
     }
 }
 
+fn read_zram_devices() -> Vec<ZramDevice> {
+    let Ok(entries) = std::fs::read_dir("/sys/block") else { return Vec::new() };
+    let mut devices: Vec<ZramDevice> = entries.flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.starts_with("zram") {
+                return None;
+            }
+            // mm_stat: orig_data_size compr_data_size mem_used_total mem_limit mem_used_max ...
+            let mm_stat = utils::read_sysfs_string(entry.path().join("mm_stat"))?;
+            let fields: Vec<u64> = mm_stat.split_whitespace().filter_map(|f| f.parse().ok()).collect();
+            if fields.len() < 3 {
+                return None;
+            }
+            let compression_ratio = if fields[1] > 0 { Some(fields[0] as f64 / fields[1] as f64) } else { None };
+            Some(ZramDevice {
+                disksize: utils::read_sysfs_value::<u64>(entry.path().join("disksize")).unwrap_or(0),
+                name,
+                orig_data_size: fields[0],
+                compr_data_size: fields[1],
+                mem_used_total: fields[2],
+                compression_ratio
+            })
+        })
+        .collect();
+    devices.sort_by(|a, b| a.name.cmp(&b.name));
+    devices
+}
+
+fn read_swap_devices() -> Vec<SwapDevice> {
+    let Ok(content) = std::fs::read_to_string("/proc/swaps") else { return Vec::new() };
+    // Filename  Type  Size  Used  Priority, sizes in KiB
+    content.lines().skip(1).filter_map(|line| {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() < 5 {
+            return None;
+        }
+        Some(SwapDevice {
+            name: parts[0].to_string(),
+            kind: parts[1].to_string(),
+            size: parts[2].parse::<u64>().ok()? * 1024,
+            used: parts[3].parse::<u64>().ok()? * 1024,
+            priority: parts[4].parse().unwrap_or(0),
+            is_zram: parts[0].starts_with("/dev/zram")
+        })
+    }).collect()
+}
+
+pub fn get_memory_detail() -> Option<MemoryDetailStats> {
+    let config: &Config = Config::global();
+    let content = std::fs::read_to_string("/proc/meminfo").ok()?;
+
+    let mut meminfo: HashMap<&str, u64> = HashMap::new();
+    for line in content.lines() {
+        if let Some((key, value)) = line.split_once(':') {
+            if let Some(kib) = value.split_whitespace().next().and_then(|v| v.parse::<u64>().ok()) {
+                meminfo.insert(key, kib * 1024);
+            }
+        }
+    }
+    let field = |key: &str| meminfo.get(key).copied().unwrap_or(0);
+
+    let total = field("MemTotal");
+    if total == 0 {
+        return None;
+    }
+    let available = field("MemAvailable");
+    let used = total.saturating_sub(available);
+    let used_percent = 100 * used / total;
+    let warn = config.threshold_ram.get_warn_level(used_percent as f64);
+
+    Some(MemoryDetailStats {
+        total,
+        free: field("MemFree"),
+        available,
+        used,
+        cached: field("Cached"),
+        buffers: field("Buffers"),
+        shared: field("Shmem"),
+        dirty: field("Dirty"),
+        writeback: field("Writeback"),
+        used_percent,
+        swaps: read_swap_devices(),
+        zram: read_zram_devices(),
+        icon: "\u{efc5}".into(), // memory
+        warn
+    })
+}
 
 pub fn get_disk_info () -> Option<DiskStats> {
     let config: &Config = Config::global();
@@ -130,6 +216,100 @@ pub fn get_sys_temperatures () -> Option<TempStats> {
     })
 }
 
+// hwmon drivers where pwmN is known to control fanN
+const PWM_FAN_MAPPED_CHIPS: [&str; 5] = ["thinkpad", "dell_smm", "amdgpu", "radeon", "nouveau"];
+
+pub fn get_fan_stats () -> Option<FanStats> {
+    let config: &Config = Config::global();
+
+    let mut chips: Vec<std::path::PathBuf> = std::fs::read_dir("/sys/class/hwmon").ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .collect();
+    chips.sort();
+
+    // First pass: hottest sensor across all chips, a stopped fan is a problem only when things are hot
+    let mut max_temp: Option<f64> = None;
+    let mut fan_ids: Vec<(std::path::PathBuf, String, u32)> = Vec::new();
+    for chip in &chips {
+        let chip_name = utils::read_sysfs_string(chip.join("name")).unwrap_or_default();
+        let Ok(entries) = std::fs::read_dir(chip) else { continue };
+        let mut ids: Vec<u32> = Vec::new();
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if let Some(id) = file_name.strip_prefix("fan").and_then(|f| f.strip_suffix("_input")) {
+                if let Ok(id) = id.parse::<u32>() {
+                    ids.push(id);
+                }
+            } else if file_name.starts_with("temp") && file_name.ends_with("_input") {
+                if let Some(millideg) = utils::read_sysfs_value::<f64>(entry.path()) {
+                    let temp = millideg / 1000.0;
+                    max_temp = Some(max_temp.map_or(temp, |t| t.max(temp)));
+                }
+            }
+        }
+        ids.sort();
+        fan_ids.extend(ids.into_iter().map(|id| (chip.clone(), chip_name.clone(), id)));
+    }
+
+    if fan_ids.is_empty() {
+        return None;
+    }
+
+    let temp_warn = max_temp.map_or(0.0, |t| config.threshold_temperature.get_warn_level(t));
+
+    let mut fans = Vec::new();
+    for (chip, chip_name, id) in fan_ids {
+        let Some(rpm) = utils::read_sysfs_value::<u32>(chip.join(format!("fan{id}_input"))) else { continue };
+        let label = utils::read_sysfs_string(chip.join(format!("fan{id}_label"))).unwrap_or_else(|| format!("fan{id}"));
+        let rpm_max = utils::read_sysfs_value::<u32>(chip.join(format!("fan{id}_max"))).filter(|m| *m > 0);
+        // pwmN drives fanN only on some chips, on Super-I/O ones (nct67xx, it87, ...) the
+        // channels can be wired to any tach input, so their duty cycle is not used there
+        let pwm_mapped = PWM_FAN_MAPPED_CHIPS.contains(&chip_name.as_str())
+            && chip.join(format!("pwm{id}_enable")).exists();
+        let pwm = if pwm_mapped {
+            utils::read_sysfs_value::<u32>(chip.join(format!("pwm{id}")))
+                .map(|raw| ((raw.min(255) as f64) * 100.0 / 255.0).round() as u8)
+        } else { None };
+
+        // Speed as a percentage of the maximum, pwm duty cycle is preferred when available
+        let speed = pwm.map(|p| p as f64)
+            .or_else(|| rpm_max.map(|m| 100.0 * rpm as f64 / m as f64));
+
+        let stopped = rpm == 0;
+        let speed_warn = speed.map_or(0.0, |s| config.threshold_fan.get_warn_level(s));
+        let at_max = speed_warn >= 1.0;
+        let warn = if stopped { temp_warn } else { speed_warn };
+
+        fans.push(FanInfo {
+            chip: chip_name,
+            label,
+            rpm,
+            rpm_max,
+            pwm,
+            stopped,
+            at_max,
+            warn
+        });
+    }
+
+    if fans.is_empty() {
+        return None;
+    }
+
+    let warn = fans.iter().map(|f| f.warn).fold(0.0, f64::max);
+    let icon = if fans.iter().any(|f| f.stopped && f.warn > 0.0) { "\u{f081d}" } // fan off
+               else if fans.iter().any(|f| f.at_max) { "\u{f146c}" } // fan alert
+               else { "\u{f0210}" }; // fan
+
+    Some(FanStats {
+        fans,
+        max_temp,
+        icon: icon.into(),
+        warn
+    })
+}
+
 // Legacy, just for legacy-ratatoskr
 #[deprecated]
 pub fn get_volume () -> Option<VolumeStats> {
@@ -152,14 +332,15 @@ pub fn get_volume () -> Option<VolumeStats> {
             value: volume.value,
             clazz: volume.clazz,
             headphones: volume.headphones,
-            warn
+            warn,
+            ..Default::default()
         })
     } else {
         Some(VolumeStats::default())
     }
 }
 
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 pub fn get_unix_time () -> u64 {
     match SystemTime::now().duration_since(UNIX_EPOCH) {
         Ok(n) => n.as_secs(),
@@ -167,38 +348,359 @@ pub fn get_unix_time () -> u64 {
     }
 }
 
+struct WeatherCache {
+    last_good: Option<WeatherStats>,
+    last_failure: Option<Instant>
+}
+
+// A failed request is not repeated before this, the updater would retry every second
+const WEATHER_RETRY: Duration = Duration::from_secs(60);
+
+static WEATHER_CACHE: Lazy<Mutex<WeatherCache>> = Lazy::new(|| Mutex::new(WeatherCache { last_good: None, last_failure: None }));
+static HTTP_AGENT: Lazy<ureq::Agent> = Lazy::new(|| {
+    ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(15))
+        .build()
+});
+
+/// Current conditions from an Open-Meteo compatible endpoint.
+/// If the request fails, the last good value is returned marked as stale.
+/// Without configured coordinates there is nothing to fetch
 pub fn get_weather () -> Option<WeatherStats> {
-    let output = Command::new("meteo.sh").arg("Desenzano Del Garda").arg("45.457692").arg("10.570684").output();
-    let stdout = String::from_utf8(output.unwrap().stdout).unwrap();
-    // println!("\n{:?}", stdout);
-    // let weather: WeatherObj;
-    if let Ok(mut weather) = serde_json::from_str::<WeatherStats>(&stdout) {
-        weather.updated = Some(format!("{}", Utc::now().to_rfc3339()));
-        let mut temp_warn: f64 = 0.0;
-        if weather.temp > 28 {
-            temp_warn = f64::clamp(weather.temp as f64 / 36.0, 0.0, 1.0);
-        } else if weather.temp < 10 {
-            temp_warn = f64::clamp((10.0 - weather.temp as f64) / 10.0 , 0.0, 1.0);
-        }
-        match weather.text.as_str() {
-            "Overcast" => { temp_warn += 0.2; },
-            "Fog" => { temp_warn += 0.3; },
-            "Depositing rime fog" => { temp_warn += 0.3; },
-            "Rain (slight)" => { temp_warn += 0.2; },
-            "Rain (moderate)" => { temp_warn += 0.4; },
-            "Rain (heavy)" => { temp_warn += 0.6; },
-            "Thunderstorm" => { temp_warn += 0.8; },
+    let config: &Config = Config::global();
+    let (Some(latitude), Some(longitude)) = (config.weather_latitude, config.weather_longitude) else {
+        return None;
+    };
+    let mut cache = WEATHER_CACHE.lock().ok()?;
+    let stale = |cache: &WeatherCache| cache.last_good.as_ref().map(|weather| WeatherStats { stale: true, ..weather.clone() });
 
-            _ => {}
+    if cache.last_failure.is_some_and(|t| t.elapsed() < WEATHER_RETRY) {
+        return stale(&cache);
+    }
+    match fetch_weather(latitude, longitude) {
+        Some(weather) => {
+            cache.last_good = Some(weather.clone());
+            cache.last_failure = None;
+            Some(weather)
+        },
+        None => {
+            cache.last_failure = Some(Instant::now());
+            stale(&cache)
         }
-        weather.warn = Some(temp_warn);
-        return Some(weather)
-    } else {
+    }
+}
+
+fn fetch_weather(latitude: f64, longitude: f64) -> Option<WeatherStats> {
+    let config: &Config = Config::global();
+
+    let mut request = HTTP_AGENT.get(&config.weather_url)
+        .query("latitude", &latitude.to_string())
+        .query("longitude", &longitude.to_string())
+        .query("current", "temperature_2m,apparent_temperature,relative_humidity_2m,weather_code,is_day,wind_speed_10m")
+        .query("hourly", "temperature_2m,precipitation_probability,weather_code,is_day,wind_speed_10m")
+        .query("daily", "weather_code,temperature_2m_max,temperature_2m_min,sunrise,sunset")
+        .query("forecast_days", "7")
+        .query("timezone", "auto");
+    if config.weather_units == WeatherUnits::Imperial {
+        request = request
+            .query("temperature_unit", "fahrenheit")
+            .query("wind_speed_unit", "mph")
+            .query("precipitation_unit", "inch");
+    }
+
+    let body = match request.call() {
+        Ok(response) => response.into_string().ok()?,
+        Err(e) => {
+            eprintln!("Weather: {e}");
+            return None;
+        }
+    };
+    let Ok(json) = serde_json::from_str::<serde_json::Value>(&body) else {
         eprintln!("Error weather");
-        eprintln!("{}", stdout);
+        eprintln!("{}", body);
+        return None;
+    };
+
+    let current = &json["current"];
+    let units = &json["current_units"];
+    let code = current["weather_code"].as_u64()? as u8;
+    let is_day = current["is_day"].as_u64().unwrap_or(1) == 1;
+    let (text, icon, icon_name) = wmo_weather(code, is_day);
+
+    // Times are local to the location (timezone=auto), e.g. "2026-10-19T07:12"
+    let now = parse_open_meteo_time(current["time"].as_str()?)?;
+    let sunrise = json["daily"]["sunrise"][0].as_str().and_then(parse_open_meteo_time);
+    let sunset = json["daily"]["sunset"][0].as_str().and_then(parse_open_meteo_time);
+    let minutes = |t: &chrono::NaiveDateTime| (t.hour() * 60 + t.minute()) as u64;
+    let now_mins = minutes(&now);
+    let sunrise_mins = sunrise.as_ref().map(minutes).unwrap_or(0);
+    let sunset_mins = sunset.as_ref().map(minutes).unwrap_or(0);
+    let daylight = if sunset_mins > sunrise_mins {
+        ((now_mins as f64 - sunrise_mins as f64) / (sunset_mins - sunrise_mins) as f64).clamp(0.0, 1.0)
+    } else { 0.0 };
+
+    let mut weather = WeatherStats {
+        icon: icon.into(),
+        icon_name: icon_name.into(),
+        code,
+        temp: current["temperature_2m"].as_f64()?.round() as i8,
+        temp_real: current["apparent_temperature"].as_f64().unwrap_or_default().round() as i8,
+        temp_unit: units["temperature_2m"].as_str().unwrap_or("°C").into(),
+        text: text.into(),
+        day: now.format("%A").to_string(),
+        sunrise: sunrise.map(|t| t.format("%H:%M").to_string()).unwrap_or_default(),
+        sunset: sunset.map(|t| t.format("%H:%M").to_string()).unwrap_or_default(),
+        sunrise_mins,
+        sunset_mins,
+        daylight,
+        locality: config.weather_locality.clone().unwrap_or_default(),
+        humidity: current["relative_humidity_2m"].as_u64().unwrap_or_default() as u8,
+        wind_speed: current["wind_speed_10m"].as_f64().unwrap_or_default(),
+        wind_unit: units["wind_speed_10m"].as_str().unwrap_or("km/h").into(),
+        updated: Some(format!("{}", Utc::now().to_rfc3339())),
+        stale: false,
+        hourly: weather_hourly(&json["hourly"], &now),
+        daily: weather_daily(&json["daily"]),
+        warn: None
+    };
+    weather.warn = Some(weather_warn(&weather));
+    Some(weather)
+}
+
+/// The 24 hourly values starting from the current hour
+fn weather_hourly(hourly: &serde_json::Value, now: &chrono::NaiveDateTime) -> Vec<WeatherHour> {
+    let Some(times) = hourly["time"].as_array() else { return Vec::new() };
+    let current_hour = now.date().and_hms_opt(now.hour(), 0, 0);
+    times.iter()
+        .enumerate()
+        .filter(|(_, time)| time.as_str().and_then(parse_open_meteo_time) >= current_hour)
+        .take(24)
+        .filter_map(|(i, time)| {
+            let code = hourly["weather_code"][i].as_u64()? as u8;
+            let is_day = hourly["is_day"][i].as_u64().unwrap_or(1) == 1;
+            let (text, icon, _) = wmo_weather(code, is_day);
+            Some(WeatherHour {
+                time: time.as_str()?.to_string(),
+                temp: hourly["temperature_2m"][i].as_f64()?,
+                precipitation_probability: hourly["precipitation_probability"][i].as_u64().map(|p| p as u8),
+                code,
+                icon: icon.into(),
+                text: text.into(),
+                wind_speed: hourly["wind_speed_10m"][i].as_f64().unwrap_or_default()
+            })
+        })
+        .collect()
+}
+
+fn weather_daily(daily: &serde_json::Value) -> Vec<WeatherDay> {
+    let Some(dates) = daily["time"].as_array() else { return Vec::new() };
+    let hh_mm = |value: &serde_json::Value| value.as_str()
+        .and_then(parse_open_meteo_time)
+        .map(|t| t.format("%H:%M").to_string())
+        .unwrap_or_default();
+    dates.iter()
+        .enumerate()
+        .take(7)
+        .filter_map(|(i, date)| {
+            let date = chrono::NaiveDate::parse_from_str(date.as_str()?, "%Y-%m-%d").ok()?;
+            let code = daily["weather_code"][i].as_u64()? as u8;
+            let (text, icon, _) = wmo_weather(code, true);
+            Some(WeatherDay {
+                date: date.format("%Y-%m-%d").to_string(),
+                day: date.format("%A").to_string(),
+                temp_min: daily["temperature_2m_min"][i].as_f64()?,
+                temp_max: daily["temperature_2m_max"][i].as_f64()?,
+                code,
+                icon: icon.into(),
+                text: text.into(),
+                sunrise: hh_mm(&daily["sunrise"][i]),
+                sunset: hh_mm(&daily["sunset"][i])
+            })
+        })
+        .collect()
+}
+
+fn parse_open_meteo_time(value: &str) -> Option<chrono::NaiveDateTime> {
+    chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M").ok()
+}
+
+fn weather_warn(weather: &WeatherStats) -> f64 {
+    let config: &Config = Config::global();
+    config.weather_warn_rules.iter()
+        .map(|rule| rule.get_warn_level(weather.code, &weather.text, weather.temp as f64, weather.humidity as f64, weather.wind_speed))
+        .sum::<f64>()
+        .clamp(0.0, 1.0)
+}
+
+/// WMO weather interpretation code -> (text, icon, freedesktop icon name)
+pub fn wmo_weather(code: u8, is_day: bool) -> (&'static str, &'static str, &'static str) {
+    match code {
+        0 if is_day => ("Clear sky", "\u{f0599}", "weather-clear"), // weather sunny
+        0 => ("Clear sky", "\u{f0594}", "weather-clear-night"), // weather night
+        1 if is_day => ("Mainly clear", "\u{f0595}", "weather-few-clouds"), // weather partly cloudy
+        1 => ("Mainly clear", "\u{f0f31}", "weather-few-clouds-night"), // weather night partly cloudy
+        2 if is_day => ("Partly cloudy", "\u{f0595}", "weather-few-clouds"),
+        2 => ("Partly cloudy", "\u{f0f31}", "weather-few-clouds-night"),
+        3 => ("Overcast", "\u{f0590}", "weather-overcast"), // weather cloudy
+        45 => ("Fog", "\u{f0591}", "weather-fog"), // weather fog
+        48 => ("Depositing rime fog", "\u{f0591}", "weather-fog"),
+        51 => ("Drizzle (light)", "\u{f0597}", "weather-showers-scattered"), // weather rainy
+        53 => ("Drizzle (moderate)", "\u{f0597}", "weather-showers-scattered"),
+        55 => ("Drizzle (dense)", "\u{f0597}", "weather-showers"),
+        56 => ("Freezing drizzle (light)", "\u{f067f}", "weather-freezing-rain"), // weather snowy rainy
+        57 => ("Freezing drizzle (dense)", "\u{f067f}", "weather-freezing-rain"),
+        61 => ("Rain (slight)", "\u{f0597}", "weather-showers-scattered"),
+        63 => ("Rain (moderate)", "\u{f0597}", "weather-showers"),
+        65 => ("Rain (heavy)", "\u{f0596}", "weather-showers"), // weather pouring
+        66 => ("Freezing rain (light)", "\u{f067f}", "weather-freezing-rain"),
+        67 => ("Freezing rain (heavy)", "\u{f067f}", "weather-freezing-rain"),
+        71 => ("Snow fall (slight)", "\u{f0598}", "weather-snow"), // weather snowy
+        73 => ("Snow fall (moderate)", "\u{f0598}", "weather-snow"),
+        75 => ("Snow fall (heavy)", "\u{f0f36}", "weather-snow"), // weather snowy heavy
+        77 => ("Snow grains", "\u{f0598}", "weather-snow"),
+        80 => ("Rain showers (slight)", "\u{f0597}", "weather-showers-scattered"),
+        81 => ("Rain showers (moderate)", "\u{f0597}", "weather-showers"),
+        82 => ("Rain showers (violent)", "\u{f0596}", "weather-showers"),
+        85 => ("Snow showers (slight)", "\u{f0598}", "weather-snow"),
+        86 => ("Snow showers (heavy)", "\u{f0f36}", "weather-snow"),
+        95 => ("Thunderstorm", "\u{f0593}", "weather-storm"), // weather lightning
+        96 => ("Thunderstorm with slight hail", "\u{f067e}", "weather-storm"), // weather lightning rainy
+        99 => ("Thunderstorm with heavy hail", "\u{f067e}", "weather-storm"),
+        _ => ("Unknown", "\u{f0590}", "weather-severe-alert")
     }
-    // WeatherStats::default()
-    return None
+}
+
+// Zenith angles of the solar events, refraction and solar disk included for sunrise/sunset
+const ZENITH_SUNRISE: f64 = 90.833;
+const ZENITH_CIVIL: f64 = 96.0;
+const ZENITH_NAUTICAL: f64 = 102.0;
+
+/// Sun position for a Julian century, NOAA solar calculator equations
+struct SolarPosition {
+    declination: f64, // degrees
+    eq_time: f64 // equation of time, minutes
+}
+
+fn solar_position(julian_century: f64) -> SolarPosition {
+    let t = julian_century;
+    let mean_long = (280.46646 + t * (36000.76983 + t * 0.0003032)).rem_euclid(360.0);
+    let mean_anom = 357.52911 + t * (35999.05029 - 0.0001537 * t);
+    let eccent = 0.016708634 - t * (0.000042037 + 0.0000001267 * t);
+    let m = mean_anom.to_radians();
+    let eq_ctr = m.sin() * (1.914602 - t * (0.004817 + 0.000014 * t))
+        + (2.0 * m).sin() * (0.019993 - 0.000101 * t)
+        + (3.0 * m).sin() * 0.000289;
+    let omega = (125.04 - 1934.136 * t).to_radians();
+    let app_long = mean_long + eq_ctr - 0.00569 - 0.00478 * omega.sin();
+    let mean_obliq = 23.0 + (26.0 + (21.448 - t * (46.815 + t * (0.00059 - t * 0.001813))) / 60.0) / 60.0;
+    let obliq = (mean_obliq + 0.00256 * omega.cos()).to_radians();
+    let declination = (obliq.sin() * app_long.to_radians().sin()).asin();
+
+    let y = (obliq / 2.0).tan().powi(2);
+    let l0 = mean_long.to_radians();
+    let eq_time = 4.0 * (y * (2.0 * l0).sin()
+        - 2.0 * eccent * m.sin()
+        + 4.0 * eccent * y * m.sin() * (2.0 * l0).cos()
+        - 0.5 * y * y * (4.0 * l0).sin()
+        - 1.25 * eccent * eccent * (2.0 * m).sin()).to_degrees();
+
+    SolarPosition { declination: declination.to_degrees(), eq_time }
+}
+
+fn julian_century(unix_secs: f64) -> f64 {
+    let julian_day = unix_secs / 86400.0 + 2440587.5;
+    (julian_day - 2451545.0) / 36525.0
+}
+
+/// Hour angle (degrees) of the sun at the given zenith, None if it is never reached today
+fn hour_angle(latitude: f64, declination: f64, zenith: f64) -> Option<f64> {
+    let lat = latitude.to_radians();
+    let decl = declination.to_radians();
+    let cos_ha = zenith.to_radians().cos() / (lat.cos() * decl.cos()) - lat.tan() * decl.tan();
+    if (-1.0..=1.0).contains(&cos_ha) { Some(cos_ha.acos().to_degrees()) } else { None }
+}
+
+/// Solar events, computed offline from weather_latitude and weather_longitude
+pub fn get_sun_stats() -> Option<SunStats> {
+    let config: &Config = Config::global();
+    let (latitude, longitude) = (config.weather_latitude?, config.weather_longitude?);
+    sun_stats(latitude, longitude, &chrono::Local::now())
+}
+
+/// Solar events of now's day, times are given in now's time zone
+fn sun_stats<Tz: chrono::TimeZone>(latitude: f64, longitude: f64, now: &chrono::DateTime<Tz>) -> Option<SunStats>
+where Tz::Offset: std::fmt::Display {
+    let midnight_utc = now.date_naive().and_hms_opt(0, 0, 0)?.and_utc().timestamp() as f64;
+
+    // Position at the approximate solar noon, then at the corrected one
+    let mut noon_mins = 720.0 - 4.0 * longitude;
+    let mut position = solar_position(julian_century(midnight_utc + noon_mins * 60.0));
+    noon_mins = 720.0 - 4.0 * longitude - position.eq_time;
+    position = solar_position(julian_century(midnight_utc + noon_mins * 60.0));
+    noon_mins = 720.0 - 4.0 * longitude - position.eq_time;
+
+    let to_local = |mins_utc: f64| {
+        chrono::DateTime::from_timestamp((midnight_utc + mins_utc * 60.0) as i64, 0)
+            .map(|t| t.with_timezone(&now.timezone()))
+    };
+    let event = |zenith: f64, sign: f64| {
+        hour_angle(latitude, position.declination, zenith).and_then(|ha| to_local(noon_mins + sign * 4.0 * ha))
+    };
+    let hh_mm = |t: &chrono::DateTime<Tz>| t.format("%H:%M").to_string();
+    let mins = |t: &chrono::DateTime<Tz>| (t.hour() * 60 + t.minute()) as u64;
+
+    let sunrise = event(ZENITH_SUNRISE, -1.0);
+    let sunset = event(ZENITH_SUNRISE, 1.0);
+    let solar_noon = to_local(noon_mins)?;
+
+    // Current elevation from the true solar time
+    let current = solar_position(julian_century(now.timestamp() as f64));
+    let now_mins_utc = (now.timestamp() as f64 - midnight_utc) / 60.0;
+    let true_solar_time = (now_mins_utc + current.eq_time + 4.0 * longitude).rem_euclid(1440.0);
+    let ha = (true_solar_time / 4.0 - 180.0).to_radians();
+    let lat = latitude.to_radians();
+    let decl = current.declination.to_radians();
+    let zenith = (lat.sin() * decl.sin() + lat.cos() * decl.cos() * ha.cos()).clamp(-1.0, 1.0).acos().to_degrees();
+    let elevation = 90.0 - zenith;
+
+    let is_day = zenith < ZENITH_SUNRISE;
+    let (day_length, daylight) = match (&sunrise, &sunset) {
+        (Some(rise), Some(set)) => {
+            let length = (set.timestamp() - rise.timestamp()) as f64;
+            let elapsed = (now.timestamp() - rise.timestamp()) as f64;
+            ((length / 60.0).round() as u64, (elapsed / length).clamp(0.0, 1.0))
+        },
+        // Polar day: the whole day is daylight
+        _ if is_day => (1440, (mins(now) as f64 / 1440.0).clamp(0.0, 1.0)),
+        // Polar night
+        _ => (0, 0.0)
+    };
+
+    let morning = *now < solar_noon;
+    let (phase, icon) = if is_day { ("day", "\u{f0599}") } // weather sunny
+        else if zenith < ZENITH_CIVIL { ("civil_twilight", if morning { "\u{f059c}" } else { "\u{f059b}" }) } // weather sunset up / down
+        else if zenith < ZENITH_NAUTICAL { ("nautical_twilight", "\u{f0594}") } // weather night
+        else if zenith < 108.0 { ("astronomical_twilight", "\u{f0594}") }
+        else { ("night", "\u{f0594}") };
+
+    Some(SunStats {
+        sunrise: sunrise.as_ref().map(hh_mm),
+        sunset: sunset.as_ref().map(hh_mm),
+        solar_noon: hh_mm(&solar_noon),
+        civil_dawn: event(ZENITH_CIVIL, -1.0).as_ref().map(hh_mm),
+        civil_dusk: event(ZENITH_CIVIL, 1.0).as_ref().map(hh_mm),
+        nautical_dawn: event(ZENITH_NAUTICAL, -1.0).as_ref().map(hh_mm),
+        nautical_dusk: event(ZENITH_NAUTICAL, 1.0).as_ref().map(hh_mm),
+        sunrise_mins: sunrise.as_ref().map(mins),
+        sunset_mins: sunset.as_ref().map(mins),
+        day_length,
+        daylight,
+        elevation,
+        is_day,
+        phase: phase.into(),
+        icon: icon.into(),
+        warn: 0.0
+    })
 }
 
 // static mut N_CPU: usize = 0;
@@ -240,7 +742,12 @@ pub fn get_load_avg() -> Option<AvgLoadStats> {
 
         let incrementing_factor = ((m1 / m5 - 1.0) as f64).clamp(-0.5, 1.0);
         let absolute_factor = (((m1 - 1.0) / (ncpu - 1.0)) as f64).clamp(0.0, 1.0);
-        let overall_factor = ((0.5 * incrementing_factor as f64) + 1.0 * absolute_factor).clamp(0.0, 1.0);
+        let mut overall_factor = ((0.5 * incrementing_factor as f64) + 1.0 * absolute_factor).clamp(0.0, 1.0);
+        if config.loadavg_use_pressure {
+            if let Some(cpu) = read_pressure("cpu") {
+                overall_factor = overall_factor.max(config.threshold_pressure_cpu.get_warn_level(cpu.some.avg10));
+            }
+        }
         // println!("0.5*{incrementing_factor} + 1.0*{absolute_factor} = {overall_factor}");
         let color = config.threshold_avg_load.get_color(overall_factor);
 
@@ -257,118 +764,425 @@ pub fn get_load_avg() -> Option<AvgLoadStats> {
     }
 }
 
+fn parse_pressure_line(line: &str) -> Option<PressureLine> {
+    // e.g. "some avg10=0.00 avg60=0.00 avg300=0.00 total=0"
+    let mut pressure = PressureLine::default();
+    for field in line.split_whitespace().skip(1) {
+        let (key, value) = field.split_once('=')?;
+        match key {
+            "avg10" => pressure.avg10 = value.parse().ok()?,
+            "avg60" => pressure.avg60 = value.parse().ok()?,
+            "avg300" => pressure.avg300 = value.parse().ok()?,
+            "total" => pressure.total = value.parse().ok()?,
+            _ => {}
+        }
+    }
+    Some(pressure)
+}
+
+fn read_pressure(resource: &str) -> Option<PressureResource> {
+    let content = std::fs::read_to_string(format!("/proc/pressure/{resource}")).ok()?;
+    let mut some = None;
+    let mut full = None;
+    for line in content.lines() {
+        if line.starts_with("some") {
+            some = parse_pressure_line(line);
+        } else if line.starts_with("full") {
+            full = parse_pressure_line(line);
+        }
+    }
+    Some(PressureResource { some: some?, full })
+}
+
+pub fn get_pressure_stats() -> Option<PressureStats> {
+    let config: &Config = Config::global();
+
+    let cpu = read_pressure("cpu");
+    let memory = read_pressure("memory");
+    let io = read_pressure("io");
+
+    if cpu.is_none() && memory.is_none() && io.is_none() {
+        // Kernel without CONFIG_PSI or booted with psi=0
+        return None;
+    }
+
+    let cpu_warn = cpu.as_ref().map_or(0.0, |p| config.threshold_pressure_cpu.get_warn_level(p.some.avg10));
+    let memory_warn = memory.as_ref().map_or(0.0, |p| config.threshold_pressure_memory.get_warn_level(p.some.avg10));
+    let io_warn = io.as_ref().map_or(0.0, |p| config.threshold_pressure_io.get_warn_level(p.some.avg10));
+    let warn = cpu_warn.max(memory_warn).max(io_warn);
+
+    Some(PressureStats {
+        cpu,
+        memory,
+        io,
+        cpu_warn,
+        memory_warn,
+        io_warn,
+        icon: "\u{f0e4}".into(), // gauge
+        warn
+    })
+}
+
+struct ThrottleState {
+    last_total: u64,
+    last_increase: Option<Instant>
+}
+
+static THROTTLE_STATE: Lazy<Mutex<Option<ThrottleState>>> = Lazy::new(|| Mutex::new(None));
+
+// How long the throttling flag stays up after the last throttling event, avoids flickering on the bar
+const THROTTLE_HOLD: Duration = Duration::from_secs(10);
+
+pub fn get_cpufreq_stats() -> Option<CpuFreqStats> {
+    let mut cpus: Vec<(u32, std::path::PathBuf)> = std::fs::read_dir("/sys/devices/system/cpu").ok()?
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let id = name.strip_prefix("cpu")?.parse::<u32>().ok()?;
+            Some((id, entry.path()))
+        })
+        .collect();
+    cpus.sort_by_key(|(id, _)| *id);
+
+    let mut cores = Vec::new();
+    let mut core_throttle: Option<u64> = None;
+    let mut package_throttle: HashMap<u32, u64> = HashMap::new();
+    for (id, path) in cpus {
+        let freq = path.join("cpufreq");
+        if let Some(cur_khz) = utils::read_sysfs_value::<u64>(freq.join("scaling_cur_freq")) {
+            cores.push(CpuCoreFreq {
+                cpu: id,
+                cur_mhz: (cur_khz / 1000) as u32,
+                min_mhz: utils::read_sysfs_value::<u64>(freq.join("scaling_min_freq")).map_or(0, |f| (f / 1000) as u32),
+                max_mhz: utils::read_sysfs_value::<u64>(freq.join("scaling_max_freq")).map_or(0, |f| (f / 1000) as u32),
+                governor: utils::read_sysfs_string(freq.join("scaling_governor"))
+            });
+        }
+
+        // thermal_throttle exists on Intel CPUs only
+        let throttle = path.join("thermal_throttle");
+        if let Some(count) = utils::read_sysfs_value::<u64>(throttle.join("core_throttle_count")) {
+            core_throttle = Some(core_throttle.unwrap_or(0) + count);
+        }
+        if let Some(count) = utils::read_sysfs_value::<u64>(throttle.join("package_throttle_count")) {
+            // Package counters are repeated on every cpu of the same package
+            let package = utils::read_sysfs_value::<u32>(path.join("topology/physical_package_id")).unwrap_or(0);
+            package_throttle.insert(package, count);
+        }
+    }
+
+    if cores.is_empty() {
+        return None;
+    }
+
+    let avg_mhz = (cores.iter().map(|c| c.cur_mhz as u64).sum::<u64>() / cores.len() as u64) as u32;
+    let max_mhz = cores.iter().map(|c| c.cur_mhz).max().unwrap_or(0);
+    let governor = cores[0].governor.clone();
+    let package_throttle = if package_throttle.is_empty() { None } else { Some(package_throttle.values().sum::<u64>()) };
+
+    let mut throttling = false;
+    if core_throttle.is_some() || package_throttle.is_some() {
+        let total = core_throttle.unwrap_or(0) + package_throttle.unwrap_or(0);
+        let now = Instant::now();
+        if let Ok(mut state) = THROTTLE_STATE.lock() {
+            let last_increase = match state.as_ref() {
+                Some(prev) if total > prev.last_total => Some(now),
+                Some(prev) => prev.last_increase,
+                None => None // First reading, counters since boot are not news
+            };
+            throttling = last_increase.is_some_and(|t| now.duration_since(t) < THROTTLE_HOLD);
+            *state = Some(ThrottleState { last_total: total, last_increase });
+        }
+    }
+
+    let warn = if throttling { 1.0 } else { 0.0 };
+    let icon = if throttling { "\u{f0f86}" } else { "\u{f04c5}" }; // speedometer slow / speedometer
+
+    Some(CpuFreqStats {
+        cores,
+        avg_mhz,
+        max_mhz,
+        governor,
+        throttle_core_count: core_throttle,
+        throttle_package_count: package_throttle,
+        throttling,
+        icon: icon.into(),
+        warn
+    })
+}
+
 use battery::{Manager, State};
 
+fn battery_state_name(state: State) -> &'static str {
+    match state {
+        State::Charging => "Charging",
+        State::Discharging => "Discharging",
+        State::Full => "Full",
+        State::Empty => "Empty",
+        State::Unknown => "Unknown",
+        State::Notcharging => "NotCharging",
+        _ => "Unexpected"
+    }
+}
+
+fn read_ac_online() -> Option<bool> {
+    let entries = std::fs::read_dir("/sys/class/power_supply").ok()?;
+    let mut found = None;
+    for entry in entries.flatten() {
+        if utils::read_sysfs_string(entry.path().join("type")).as_deref() != Some("Mains") {
+            continue;
+        }
+        let online = utils::read_sysfs_value::<u8>(entry.path().join("online")).unwrap_or(0) == 1;
+        found = Some(found.unwrap_or(false) || online);
+    }
+    found
+}
+
+struct SysfsBattery {
+    name: String,
+    model: Option<String>,
+    serial: Option<String>,
+    charge_start_threshold: Option<u8>,
+    charge_end_threshold: Option<u8>
+}
+
+// Left in read_dir order, the one of the battery crate (kernfs does not list by name)
+fn read_sysfs_batteries() -> Vec<SysfsBattery> {
+    let Ok(entries) = std::fs::read_dir("/sys/class/power_supply") else { return Vec::new() };
+    entries.flatten()
+        .filter(|entry| utils::read_sysfs_string(entry.path().join("type")).as_deref() == Some("Battery"))
+        // Peripherals (hid, bluetooth) are reported by upower, here we only want system batteries
+        .filter(|entry| utils::read_sysfs_string(entry.path().join("scope")).as_deref() != Some("Device"))
+        .map(|entry| {
+            let path = entry.path();
+            SysfsBattery {
+                name: entry.file_name().to_string_lossy().to_string(),
+                model: utils::read_sysfs_string(path.join("model_name")),
+                serial: utils::read_sysfs_string(path.join("serial_number")),
+                charge_start_threshold: utils::read_sysfs_value::<u8>(path.join("charge_control_start_threshold")),
+                charge_end_threshold: utils::read_sysfs_value::<u8>(path.join("charge_control_end_threshold"))
+            }
+        })
+        .collect()
+}
+
+struct BatteryHistory {
+    state: Option<State>,
+    samples: VecDeque<(f64, f32)> // (unix time in seconds, energy)
+}
+
+static BATTERY_HISTORY: Lazy<Mutex<BatteryHistory>> = Lazy::new(|| Mutex::new(BatteryHistory { state: None, samples: VecDeque::new() }));
+
+// A longer hole between samples means the machine was suspended, old samples are useless then
+const BATTERY_SAMPLE_GAP: f64 = 30.0;
+// Minimum time span of the samples before trusting the regression
+const BATTERY_MIN_SPAN: f64 = 60.0;
+
+// Least squares slope of energy over time, that is the power in W
+fn energy_slope(samples: &VecDeque<(f64, f32)>) -> Option<f32> {
+    let n = samples.len() as f64;
+    let t0 = samples.front()?.0;
+    let mean_t = samples.iter().map(|(t, _)| t - t0).sum::<f64>() / n;
+    let mean_e = samples.iter().map(|(_, e)| *e as f64).sum::<f64>() / n;
+    let (mut num, mut den) = (0.0, 0.0);
+    for (t, e) in samples {
+        let dt = t - t0 - mean_t;
+        num += dt * (*e as f64 - mean_e);
+        den += dt * dt;
+    }
+    if den > 0.0 { Some((num / den) as f32) } else { None }
+}
+
+// Returns smoothed power, smoothed eta (minutes) and the source of the eta
+fn smoothed_battery_eta(energy: f32, capacity: f32, state: State, firmware_eta: Option<f32>) -> (Option<f32>, Option<f32>, String) {
+    let config: &Config = Config::global();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+
+    let mut slope = None;
+    if let Ok(mut history) = BATTERY_HISTORY.lock() {
+        let gap = history.samples.back().is_some_and(|(t, _)| now - t > BATTERY_SAMPLE_GAP);
+        if gap || history.state != Some(state) {
+            history.samples.clear();
+            history.state = Some(state);
+        }
+        history.samples.push_back((now, energy));
+        let window = config.battery_eta_window as f64;
+        while history.samples.front().is_some_and(|(t, _)| now - t > window) {
+            history.samples.pop_front();
+        }
+        let span = history.samples.front().map_or(0.0, |(t, _)| now - t);
+        if span >= BATTERY_MIN_SPAN {
+            slope = energy_slope(&history.samples);
+        }
+    }
+
+    let eta = slope.and_then(|w| eta_from_slope(energy, capacity, state, w));
+
+    match (eta, firmware_eta) {
+        (Some(eta), _) => (slope.map(f32::abs), Some(eta), "history".into()),
+        (None, Some(eta)) => (None, Some(eta), "firmware".into()),
+        (None, None) => (None, None, "none".into())
+    }
+}
+
+// ETA in minutes from the regression slope (W, negative while discharging)
+fn eta_from_slope(energy: f32, capacity: f32, state: State, slope: f32) -> Option<f32> {
+    match state {
+        State::Discharging if slope < 0.0 => Some(energy / -slope / 60.0),
+        State::Charging if slope > 0.0 => Some((capacity - energy).max(0.0) / slope / 60.0),
+        _ => None
+    }
+}
+
+fn battery_health(capacity: f32, capacity_design: f32) -> Option<f32> {
+    if capacity > 0.0 && capacity_design > 0.0 { Some(100.0 * capacity / capacity_design) } else { None }
+}
+
+// ETA in minutes of all the packs together, energy in J and power in W
+fn combined_battery_eta(units: &[BatteryUnit], state: State, energy: f32, capacity: f32, watt: f32) -> Option<f32> {
+    if units.len() == 1 {
+        return units[0].eta;
+    }
+    if watt <= 0.0 {
+        return None;
+    }
+    match state {
+        State::Discharging => Some(energy / watt / 60.0), // J / W = s, then minuti
+        State::Charging => Some((capacity - energy).max(0.0) / watt / 60.0),
+        _ => None
+    }
+}
+
 pub fn get_battery() -> Option<BatteryStats> {
+    let ac_online = read_ac_online();
+
     let manager = match Manager::new() {
         Ok(m) => m,
         Err(_) => {
             return Some(BatteryStats {
-                percentage: 0,
-                capacity: 0.0,
-                capacity_design: 0.0,
-                cycles: None,
-                eta: None,
                 state: "no_manager".to_string(),
+                ac_online,
                 icon: "󰂑".to_string(),
-                color: None,
-                watt: 0.0,
-                warn: 0.0
+                ..Default::default()
             })
         }
     };
 
-    let batteries = manager.batteries();
+    let batteries: Vec<battery::Battery> = match manager.batteries() {
+        Ok(batteries) => batteries.flatten().collect(),
+        Err(_) => Vec::new()
+    };
 
-    if batteries.is_err() {
+    if batteries.is_empty() {
         return Some(BatteryStats {
-            percentage: 0,
-            capacity: 0.0,
-            capacity_design: 0.0,
-            cycles: None,
-            eta: None,
             state: "no_battery".to_string(),
-            icon: "".to_string(),
-            color: None,
-            watt: 0.0,
-            warn: 0.0
+            ac_online,
+            icon: "".to_string(),
+            ..Default::default()
         });
     }
 
-    if let Some(Ok(battery)) = batteries.unwrap().next() {
-        let config: &Config = Config::global();
+    let config: &Config = Config::global();
+
+    // The battery crate does not expose the sysfs node, packs are matched by serial and model,
+    // falling back to the order: both list /sys/class/power_supply with read_dir and keep its order
+    let mut sysfs_batteries = read_sysfs_batteries();
+    let units: Vec<BatteryUnit> = batteries.iter().map(|battery| {
+        let serial = battery.serial_number().map(|s| s.trim().to_string());
+        let model = battery.model().map(String::from);
+        let sysfs_idx = sysfs_batteries.iter()
+            .position(|b| b.serial.is_some() && b.serial == serial && b.model == model)
+            .or_else(|| if sysfs_batteries.is_empty() { None } else { Some(0) });
+        let sysfs = sysfs_idx.map(|i| sysfs_batteries.remove(i));
 
-        let percentage = ((battery.state_of_charge().value * 100.0) as f32).round() as i32;
         let capacity = battery.energy_full().value as f32;
         let capacity_design = battery.energy_full_design().value as f32;
-        let watt = battery.energy_rate().value as f32;
-        let cycles = battery.cycle_count();
-
-        let eta = match battery.time_to_empty().or(battery.time_to_full()) {
-            Some(t) => Some((t.value as f32) / 60.0),  // Converte da secondi a minuti
-            _ => None,
-        };
-
-        let state = match battery.state() {
-            State::Charging => "Charging",
-            State::Discharging => "Discharging",
-            State::Full => "Full",
-            State::Empty => "Empty",
-            State::Unknown => "Unknown",
-            State::Notcharging => "NotCharging",
-            _ => "Unexpected"
-        }
-        .to_string();
-
-        let icon = (match battery.state() {
-            State::Charging => "󰂄",
-            State::Discharging => {
-                if percentage < 15 { "󰁺" }
-                else if percentage < 25 { "󰁻" }
-                else if percentage < 35 { "󰁼" }
-                else if percentage < 45 { "󰁽" }
-                else if percentage < 55 { "󰁾" }
-                else if percentage < 65 { "󰁿" }
-                else if percentage < 75 { "󰂀" }
-                else if percentage < 85 { "󰂁" }
-                else if percentage < 95 { "󰂂" }
-                else { "󰁹" }
-            },
-            State::Full | State::Notcharging => "󱟢",
-            State::Empty => "Empty",
-            State::Unknown => "󰂑",
-            // State::Unknown => "󰂑",
-            _ => "󱧥"
-        }).to_string();
-
-        let color = Some(config.threshold_battery.get_color(percentage as f64)); // utils::get_color_gradient(20.0, 70.0, percentage as f64, true)
-
-        Some(BatteryStats {
-            percentage,
+        let health = battery_health(capacity, capacity_design);
+
+        BatteryUnit {
+            name: sysfs.as_ref().map(|b| b.name.clone()),
+            vendor: battery.vendor().map(String::from),
+            model,
+            serial,
+            technology: battery.technology().to_string(),
+            health,
+            health_warn: health.map_or(0.0, |h| config.threshold_battery_health.get_warn_level(h as f64)),
+            charge_start_threshold: sysfs.as_ref().and_then(|b| b.charge_start_threshold),
+            charge_end_threshold: sysfs.as_ref().and_then(|b| b.charge_end_threshold),
+            percentage: ((battery.state_of_charge().value * 100.0) as f32).round() as i32,
+            energy: battery.energy().value as f32,
             capacity,
             capacity_design,
-            cycles,
-            eta,
-            state,
-            icon,
-            color,
-            watt,
-            warn: config.threshold_battery.get_warn_level(percentage as f64)
-        })
-    } else {
-        Some(BatteryStats {
-            percentage: 0,
-            capacity: 0.0,
-            capacity_design: 0.0,
-            cycles: None,
-            eta: None,
-            state: "no_battery".to_string(),
-            icon: "".to_string(),
-            color: None,
-            watt: 0.0,
-            warn: 0.0
-        })
-    }
+            cycles: battery.cycle_count(),
+            eta: battery.time_to_empty().or(battery.time_to_full()).map(|t| (t.value as f32) / 60.0), // Converte da secondi a minuti
+            state: battery_state_name(battery.state()).to_string(),
+            watt: battery.energy_rate().value as f32
+        }
+    }).collect();
+
+    let energy: f32 = units.iter().map(|u| u.energy).sum();
+    let capacity: f32 = units.iter().map(|u| u.capacity).sum();
+    let capacity_design: f32 = units.iter().map(|u| u.capacity_design).sum();
+    let watt: f32 = units.iter().map(|u| u.watt).sum();
+
+    let percentage = if capacity > 0.0 { (100.0 * energy / capacity).round() as i32 } else { units[0].percentage };
+    let health = battery_health(capacity, capacity_design);
+    let health_warn = health.map_or(0.0, |h| config.threshold_battery_health.get_warn_level(h as f64));
+
+    // With more packs the laptop drains (or charges) one at a time, so the overall state is the "busiest" one
+    let states: Vec<State> = batteries.iter().map(|b| b.state()).collect();
+    let combined_state = if states.contains(&State::Charging) { State::Charging }
+                         else if states.contains(&State::Discharging) { State::Discharging }
+                         else if states.iter().all(|s| *s == State::Full) { State::Full }
+                         else { states[0] };
+
+    let eta = combined_battery_eta(&units, combined_state, energy, capacity, watt);
+
+    let (watt_smoothed, eta_smoothed, eta_smoothed_source) = smoothed_battery_eta(energy, capacity, combined_state, eta);
+
+    let icon = (match combined_state {
+        State::Charging => "󰂄",
+        State::Discharging => {
+            if percentage < 15 { "󰁺" }
+            else if percentage < 25 { "󰁻" }
+            else if percentage < 35 { "󰁼" }
+            else if percentage < 45 { "󰁽" }
+            else if percentage < 55 { "󰁾" }
+            else if percentage < 65 { "󰁿" }
+            else if percentage < 75 { "󰂀" }
+            else if percentage < 85 { "󰂁" }
+            else if percentage < 95 { "󰂂" }
+            else { "󰁹" }
+        },
+        State::Full | State::Notcharging => "󱟢",
+        State::Empty => "Empty",
+        State::Unknown => "󰂑",
+        // State::Unknown => "󰂑",
+        _ => "󱧥"
+    }).to_string();
+
+    let color = Some(config.threshold_battery.get_color(percentage as f64)); // utils::get_color_gradient(20.0, 70.0, percentage as f64, true)
+
+    Some(BatteryStats {
+        percentage,
+        energy,
+        capacity,
+        capacity_design,
+        cycles: units[0].cycles,
+        eta,
+        eta_smoothed,
+        eta_smoothed_source,
+        watt_smoothed,
+        state: battery_state_name(combined_state).to_string(),
+        ac_online,
+        health,
+        health_warn,
+        batteries: units,
+        icon,
+        color,
+        watt,
+        warn: config.threshold_battery.get_warn_level(percentage as f64)
+    })
 }
 
 // use std::process::Command;
@@ -452,112 +1266,296 @@ fn get_ip(iface: &str) -> Option<String> {
     text.lines().next().map(|s| s.split('/').next().unwrap_or("").to_string())
 }
 
-pub fn get_brightness_stats() -> Option<EmbeddedDisplayStats> {
-    
-    let output = Command::new("sh")
-        .arg("-c")
-        .arg("brightnessctl g; brightnessctl m")
-        .output()
-        .ok()?;
-
-    if !output.status.success() {
-        return None;
-    }
+fn read_backlight_devices() -> Vec<BacklightDevice> {
+    let Ok(entries) = std::fs::read_dir("/sys/class/backlight") else { return Vec::new() };
+    let mut devices: Vec<BacklightDevice> = entries.flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let brightness_current = utils::read_sysfs_value::<u32>(path.join("brightness"))?;
+            let brightness_max = utils::read_sysfs_value::<u32>(path.join("max_brightness")).filter(|m| *m > 0)?;
+            Some(BacklightDevice {
+                name: entry.file_name().to_string_lossy().to_string(),
+                kind: utils::read_sysfs_string(path.join("type")).unwrap_or_default(),
+                brightness_current,
+                brightness_max,
+                perc: (100.0 * (brightness_current as f32) / (brightness_max as f32)).round() as u8
+            })
+        })
+        .collect();
+    devices.sort_by(|a, b| a.name.cmp(&b.name));
+    devices
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let mut lines = stdout.lines();
+pub fn get_brightness_stats() -> Option<EmbeddedDisplayStats> {
+    let config: &Config = Config::global();
+    let devices = read_backlight_devices();
 
-    let brightness_current = lines.next()?.trim().parse::<u32>().ok()?;
-    let brightness_max = lines.next()?.trim().parse::<u32>().ok()?;
-    let perc = (100.0 * (brightness_current as f32) / (brightness_max as f32)).round() as u8;
+    // The configured device wins, otherwise the kernel's preferred order: firmware, platform, raw
+    let kind_priority = |kind: &str| match kind { "firmware" => 0, "platform" => 1, "raw" => 2, _ => 3 };
+    let primary = config.backlight_device.as_ref()
+        .and_then(|name| devices.iter().find(|d| &d.name == name))
+        .or_else(|| devices.iter().min_by_key(|d| kind_priority(&d.kind)))?
+        .clone();
 
-    let icons = ["", "", "", "", "", "", "", "", "", "", "", "", "", ""];
-    let icon_idx = ((brightness_current as f32) / (brightness_max as f32) * (icons.len() as f32 - 1.0)).round() as usize;
-    let icon = icons[icon_idx].into();
+    let icons = ["", "", "", "", "", "", "", "", "", "", "", "", "", ""];
+    let icon_idx = ((primary.brightness_current as f32) / (primary.brightness_max as f32) * (icons.len() as f32 - 1.0)).round() as usize;
+    let icon = icons[icon_idx.min(icons.len() - 1)].into();
 
     Some(EmbeddedDisplayStats {
-        brightness_current,
-        brightness_max,
-        perc,
+        device: primary.name,
+        brightness_current: primary.brightness_current,
+        brightness_max: primary.brightness_max,
+        perc: primary.perc,
+        devices,
         icon,
         warn: 0.0
     })
 }
 
+pub fn get_led_stats() -> Option<LedStats> {
+    let mut entries: Vec<(String, std::path::PathBuf)> = std::fs::read_dir("/sys/class/leds").ok()?
+        .flatten()
+        .map(|entry| (entry.file_name().to_string_lossy().to_string(), entry.path()))
+        .collect();
+    entries.sort();
+
+    let mut kbd_backlight = None;
+    let mut found_lock_led = false;
+    let (mut capslock, mut numlock, mut scrolllock) = (false, false, false);
+    for (name, path) in entries {
+        // Every keyboard has its own lock leds (input3::capslock, input7::capslock, ...), any of them lit counts
+        let lit = || utils::read_sysfs_value::<u32>(path.join("brightness")).is_some_and(|b| b > 0);
+        if name.ends_with("::capslock") {
+            found_lock_led = true;
+            capslock |= lit();
+        } else if name.ends_with("::numlock") {
+            found_lock_led = true;
+            numlock |= lit();
+        } else if name.ends_with("::scrolllock") {
+            found_lock_led = true;
+            scrolllock |= lit();
+        } else if name.contains("kbd_backlight") && kbd_backlight.is_none() {
+            let brightness_current = utils::read_sysfs_value::<u32>(path.join("brightness"));
+            let brightness_max = utils::read_sysfs_value::<u32>(path.join("max_brightness")).filter(|m| *m > 0);
+            if let (Some(brightness_current), Some(brightness_max)) = (brightness_current, brightness_max) {
+                kbd_backlight = Some(KbdBacklight {
+                    device: name,
+                    brightness_current,
+                    brightness_max,
+                    perc: (100.0 * (brightness_current as f32) / (brightness_max as f32)).round() as u8
+                });
+            }
+        }
+    }
 
-use niri_ipc::{
-    socket::SOCKET_PATH_ENV,
-    state::{EventStreamState, EventStreamStatePart},
-    Event, Request,
-};
-use std::env;
-use std::io::{BufRead, BufReader, Write};
-use std::os::unix::net::UnixStream;
-use std::sync::{Arc, Mutex};
-
-pub fn get_niri_situation () -> std::io::Result<Arc<Mutex<EventStreamState>>> {
-    let socket_path = env::var(SOCKET_PATH_ENV).expect("Variabile d'ambiente NIRI_SOCKET non impostata");
+    if kbd_backlight.is_none() && !found_lock_led {
+        return None;
+    }
 
-    // Connetti al socket
-    let stream = UnixStream::connect(socket_path)?;
-    let reader = BufReader::new(stream.try_clone()?);
-    let mut writer = stream;
+    let icon = if capslock { "\u{f0633}" } else { "\u{f030c}" }; // caps lock / keyboard
 
-    // Invia la richiesta per avviare il flusso di eventi
-    let request = serde_json::to_string(&Request::EventStream).unwrap();
-    writeln!(writer, "{}", request)?;
-    writer.flush()?;
+    Some(LedStats {
+        kbd_backlight,
+        capslock,
+        numlock,
+        scrolllock,
+        icon: icon.into(),
+        warn: 0.0
+    })
+}
 
-    // Inizializza lo stato degli eventi
-    let state = Arc::new(Mutex::new(EventStreamState::default()));
-    let state_clone = Arc::clone(&state);
-
-    std::thread::spawn(move || {
-        // Leggi e gestisci gli eventi in arrivo
-        for line in reader.lines() {
-            let line = match line {
-                Ok(l) => {
-                    // println!("line: {:?}", &l);
-                    l
-                },
-                Err(e) => {
-                    eprintln!("Read error: {e}");
-                    continue;
-                }
-            };
-
-            if line.trim() == r#"{"Ok":"Handled"}"# {
-                continue;
+pub fn spawn_backlight_listener(tx: Sender<EmbeddedDisplayStats>) {
+    if let Some(obj) = get_brightness_stats() {
+        let _ = tx.send(obj);
+    }
+    thread::spawn(move || {
+        // The kernel emits a "change" uevent on every brightness write, from hotkeys and from sysfs alike
+        let child = Command::new("udevadm")
+            .args(["monitor", "--kernel", "--subsystem-match=backlight"])
+            .stdout(Stdio::piped())
+            .spawn();
+
+        if let Ok(mut child) = child {
+            if let Some(stdout) = child.stdout.take() {
+                let reader = BufReader::new(stdout);
+                // e.g. "KERNEL[1234.567890] change   /devices/pci0000:00/.../backlight/intel_backlight (backlight)"
+                for line in reader.lines().map_while(Result::ok) {
+                    if !line.starts_with("KERNEL[") {
+                        continue;
+                    }
+                    if let Some(obj) = get_brightness_stats() {
+                        if tx.send(obj).is_err() {
+                            let _ = child.kill();
+                            return;
+                        }
+                    }
+                }
             }
+            let _ = child.kill();
+            let _ = child.wait();
+        }
 
-            /*
-            let event: Event = match serde_json::from_str(&line) {
-                Ok(e) => e,
-                Err(e) => {
-                    eprintln!("Errore nel parsing dell'evento: {e}\nContenuto: {line}");
-                    continue;
+        eprintln!("udevadm monitor unavailable, falling back to backlight polling");
+        let mut last: Option<(String, u32)> = None;
+        loop {
+            if let Some(obj) = get_brightness_stats() {
+                let current = Some((obj.device.clone(), obj.brightness_current));
+                if current != last {
+                    last = current;
+                    if tx.send(obj).is_err() {
+                        return;
+                    }
                 }
-            };
-            */
-
-            let evt = serde_json::from_str(&line);
-            // println!("evt: {:?}", &evt);
-            let event: Event = evt.unwrap();
-            let mut s = state_clone.lock().unwrap();
-            s.apply(event);
-
-            // Stampa la lista aggiornata delle finestre
-            /* println!("Finestre attuali:");
-            for window in s.windows.windows.values() {
-                println!(
-                    "- ID: {}, Titolo: {:?}, App: {:?}",
-                    window.id, window.title, window.app_id
-                );
-            } */
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+    });
+}
+
+use niri_ipc::{
+    socket::SOCKET_PATH_ENV,
+    state::{EventStreamState, EventStreamStatePart},
+    Event, Request,
+};
+use std::env;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::Mutex;
+
+const NIRI_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const NIRI_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// Pushes workspaces, windows and the focused window every time the niri event stream changes them.
+/// If niri is not reachable (or restarts), the connection is retried with an exponential backoff
+pub fn spawn_niri_listener(tx_workspaces: Sender<WorkspacesStats>, tx_windows: Sender<WindowsStats>, tx_focused: Sender<FocusedWindowStats>) {
+    // Set by niri for its session, it cannot appear later: nothing to retry outside niri
+    let Ok(socket_path) = env::var(SOCKET_PATH_ENV) else {
+        eprintln!("{SOCKET_PATH_ENV} is not set, not a niri session: no workspaces and windows");
+        return;
+    };
+    thread::spawn(move || {
+        let mut backoff = NIRI_BACKOFF_MIN;
+        loop {
+            let started = Instant::now();
+            match run_niri_listener(&socket_path, &tx_workspaces, &tx_windows, &tx_focused) {
+                Ok(()) => eprintln!("niri event stream closed"),
+                Err(e) => eprintln!("niri listener: {e}")
+            }
+            // A connection that lasted a while was a good one, start again from the shortest wait
+            if started.elapsed() > NIRI_BACKOFF_MAX {
+                backoff = NIRI_BACKOFF_MIN;
+            }
+            thread::sleep(backoff);
+            backoff = (backoff * 2).min(NIRI_BACKOFF_MAX);
         }
     });
+}
+
+fn run_niri_listener(socket_path: &str, tx_workspaces: &Sender<WorkspacesStats>, tx_windows: &Sender<WindowsStats>, tx_focused: &Sender<FocusedWindowStats>) -> std::io::Result<()> {
+    let stream = UnixStream::connect(socket_path)?;
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    let request = serde_json::to_string(&Request::EventStream)?;
+    writeln!(writer, "{}", request)?;
+    writer.flush()?;
+
+    let mut state = EventStreamState::default();
+    let mut last_workspaces: Option<WorkspacesStats> = None;
+    let mut last_windows: Option<WindowsStats> = None;
+    let mut last_focused: Option<FocusedWindowStats> = None;
+
+    for line in reader.lines() {
+        let line = line?;
+
+        if line.trim() == r#"{"Ok":"Handled"}"# {
+            continue;
+        }
+
+        let event: Event = match serde_json::from_str(&line) {
+            Ok(e) => e,
+            Err(e) => {
+                // A reply error, or an event added by a newer niri
+                if line.starts_with(r#"{"Err""#) {
+                    return Err(std::io::Error::other(line));
+                }
+                eprintln!("niri: unknown event ({e}): {line}");
+                continue;
+            }
+        };
+        state.apply(event);
+
+        // Snapshots are compared, so only the resources that really changed are sent
+        let workspaces = niri_workspaces_stats(&state);
+        if last_workspaces.as_ref() != Some(&workspaces) {
+            if tx_workspaces.send(workspaces.clone()).is_err() {
+                break;
+            }
+            last_workspaces = Some(workspaces);
+        }
+        let windows = niri_windows_stats(&state);
+        let focused = FocusedWindowStats {
+            window: windows.windows.iter().find(|w| w.is_focused).cloned(),
+            icon: "".into(),
+            warn: 0.0
+        };
+        if last_windows.as_ref() != Some(&windows) {
+            if tx_windows.send(windows.clone()).is_err() {
+                break;
+            }
+            last_windows = Some(windows);
+        }
+        if last_focused.as_ref() != Some(&focused) {
+            if tx_focused.send(focused.clone()).is_err() {
+                break;
+            }
+            last_focused = Some(focused);
+        }
+    }
+    Ok(())
+}
 
-    Ok(state)
+fn niri_workspaces_stats(state: &EventStreamState) -> WorkspacesStats {
+    let mut workspaces: Vec<NiriWorkspace> = state.workspaces.workspaces.values()
+        .map(|w| NiriWorkspace {
+            id: w.id,
+            idx: w.idx,
+            name: w.name.clone(),
+            output: w.output.clone(),
+            is_active: w.is_active,
+            is_focused: w.is_focused,
+            is_urgent: w.is_urgent,
+            active_window_id: w.active_window_id
+        })
+        .collect();
+    workspaces.sort_by(|a, b| (&a.output, a.idx).cmp(&(&b.output, b.idx)));
+    WorkspacesStats {
+        focused: workspaces.iter().find(|w| w.is_focused).map(|w| w.id),
+        warn: if workspaces.iter().any(|w| w.is_urgent) { 1.0 } else { 0.0 },
+        workspaces,
+        icon: "".into()
+    }
+}
+
+fn niri_windows_stats(state: &EventStreamState) -> WindowsStats {
+    let mut windows: Vec<NiriWindow> = state.windows.windows.values()
+        .map(|w| NiriWindow {
+            id: w.id,
+            title: w.title.clone(),
+            app_id: w.app_id.clone(),
+            pid: w.pid,
+            workspace_id: w.workspace_id,
+            is_focused: w.is_focused,
+            is_floating: w.is_floating,
+            is_urgent: w.is_urgent
+        })
+        .collect();
+    windows.sort_by_key(|w| (w.workspace_id, w.id));
+    WindowsStats {
+        warn: if windows.iter().any(|w| w.is_urgent) { 1.0 } else { 0.0 },
+        windows,
+        icon: "".into()
+    }
 }
 
 // #[derive(Debug, Clone, Default)]
@@ -567,101 +1565,557 @@ use std::{
     thread,
 };
 
-pub fn spawn_volume_listener(tx: Sender<VolumeStats>) {
-    if let Some(obj) = read_volume_obj() {
-        let _ = tx.send(obj);
-    }
-    thread::spawn(move || {
-        let mut child = Command::new("pactl")
-            .arg("subscribe")
-            .stdout(Stdio::piped())
-            .spawn()
-            .expect("pactl subscribe failed");
+// Audio
 
-        let stdout = child.stdout.take().unwrap();
-        let reader = BufReader::new(stdout);
+use libpulse_binding::{
+    callbacks::ListResult,
+    context::{Context, FlagSet as ContextFlagSet, State as ContextState},
+    context::introspect::{Introspector, SinkInfo, SourceInfo},
+    context::subscribe::{Facility, InterestMaskSet},
+    mainloop::standard::{IterateResult, Mainloop},
+    volume::{ChannelVolumes, Volume},
+};
+use std::{cell::{Cell, RefCell}, rc::Rc};
 
-        for line in reader.lines().flatten() {
-            // filtro eventi inutili
-            if !line.contains("sink") && !line.contains("server") {
-                continue;
+pub fn spawn_volume_listener(tx: Sender<AudioUpdate>) {
+    thread::spawn(move || {
+        // The pulse objects are not Send, they live and die in this thread.
+        // If the server goes away (e.g. pipewire restart) we just connect again.
+        loop {
+            if let Err(e) = run_pulse_listener(&tx) {
+                eprintln!("PulseAudio listener: {e}");
             }
+            thread::sleep(Duration::from_secs(2));
+        }
+    });
+}
 
-            if let Some(obj) = read_volume_obj() {
-                let _ = tx.send(obj);
+fn run_pulse_listener(tx: &Sender<AudioUpdate>) -> Result<(), String> {
+    let mut mainloop = Mainloop::new().ok_or("cannot create mainloop")?;
+    let mut context = Context::new(&mainloop, "ratatoskr").ok_or("cannot create context")?;
+    context.connect(None, ContextFlagSet::NOFLAGS, None).map_err(|e| format!("connection failed: {e}"))?;
+
+    loop {
+        iterate_pulse_mainloop(&mut mainloop, &context)?;
+        if context.get_state() == ContextState::Ready {
+            break;
+        }
+    }
+
+    // Subscription events only tell us that something changed, the actual values are
+    // requested from the main loop below (the context cannot be borrowed in the callback)
+    let sink_dirty = Rc::new(Cell::new(true));
+    let source_dirty = Rc::new(Cell::new(true));
+    let streams_dirty = Rc::new(Cell::new(true));
+    let sink_dirty_cb = Rc::clone(&sink_dirty);
+    let source_dirty_cb = Rc::clone(&source_dirty);
+    let streams_dirty_cb = Rc::clone(&streams_dirty);
+    context.set_subscribe_callback(Some(Box::new(move |facility, _operation, _index| {
+        match facility {
+            Some(Facility::Sink) => sink_dirty_cb.set(true),
+            Some(Facility::Source) | Some(Facility::SourceOutput) => source_dirty_cb.set(true),
+            Some(Facility::SinkInput) => streams_dirty_cb.set(true),
+            // Server events are sent when the default sink or source changes
+            Some(Facility::Server) => {
+                sink_dirty_cb.set(true);
+                source_dirty_cb.set(true);
+            },
+            _ => {}
+        }
+    })));
+    context.subscribe(
+        InterestMaskSet::SINK | InterestMaskSet::SOURCE | InterestMaskSet::SOURCE_OUTPUT | InterestMaskSet::SINK_INPUT | InterestMaskSet::SERVER,
+        |_| {}
+    );
+
+    let introspector = context.introspect();
+    loop {
+        if sink_dirty.replace(false) {
+            let tx = tx.clone();
+            introspector.get_sink_info_by_name("@DEFAULT_SINK@", move |result| {
+                if let ListResult::Item(info) = result {
+                    let _ = tx.send(AudioUpdate::Volume(volume_stats_from_sink(info)));
+                }
+            });
+        }
+        if source_dirty.replace(false) {
+            request_microphone_stats(&introspector, tx.clone());
+        }
+        if streams_dirty.replace(false) {
+            request_audio_streams(&introspector, tx.clone());
+        }
+        iterate_pulse_mainloop(&mut mainloop, &context)?;
+    }
+}
+
+#[derive(Default)]
+struct MicrophoneQuery {
+    source: Option<MicrophoneStats>,
+    monitors: Vec<u32>,
+    recording_apps: Vec<String>
+}
+
+fn request_microphone_stats(introspector: &Introspector, tx: Sender<AudioUpdate>) {
+    // Replies come back in request order, so when the source output list ends
+    // the default source and the monitor list have already been collected
+    let query: Rc<RefCell<MicrophoneQuery>> = Rc::default();
+
+    let q = Rc::clone(&query);
+    introspector.get_source_info_by_name("@DEFAULT_SOURCE@", move |result| {
+        if let ListResult::Item(info) = result {
+            q.borrow_mut().source = Some(microphone_stats_from_source(info));
+        }
+    });
+
+    let q = Rc::clone(&query);
+    introspector.get_source_info_list(move |result| {
+        if let ListResult::Item(info) = result {
+            if info.monitor_of_sink.is_some() {
+                q.borrow_mut().monitors.push(info.index);
             }
         }
     });
+
+    let q = Rc::clone(&query);
+    introspector.get_source_output_info_list(move |result| {
+        match result {
+            ListResult::Item(info) => {
+                let mut q = q.borrow_mut();
+                // Visualizers and volume meters record from monitors, that is not the microphone
+                if q.monitors.contains(&info.source) {
+                    return;
+                }
+                if info.proplist.get_str("application.id").as_deref() == Some("org.PulseAudio.pavucontrol") {
+                    return;
+                }
+                let app = info.proplist.get_str("application.name")
+                    .or_else(|| info.name.as_ref().map(|n| n.to_string()))
+                    .unwrap_or_else(|| "Unknown".into());
+                if !q.recording_apps.contains(&app) {
+                    q.recording_apps.push(app);
+                }
+            },
+            ListResult::End => {
+                let mut q = q.borrow_mut();
+                if let Some(mut mic) = q.source.take() {
+                    mic.recording_apps = std::mem::take(&mut q.recording_apps);
+                    mic.in_use = !mic.recording_apps.is_empty();
+                    if mic.in_use && !mic.muted {
+                        mic.icon = "\u{f036c}".into(); // microphone
+                    }
+                    let _ = tx.send(AudioUpdate::Microphone(mic));
+                }
+            },
+            ListResult::Error => {}
+        }
+    });
+}
+
+fn request_audio_streams(introspector: &Introspector, tx: Sender<AudioUpdate>) {
+    let mut streams: Vec<AudioStream> = Vec::new();
+    introspector.get_sink_input_info_list(move |result| {
+        match result {
+            ListResult::Item(info) => {
+                let application = info.proplist.get_str("application.name")
+                    .or_else(|| info.name.as_ref().map(|n| n.to_string()))
+                    .unwrap_or_else(|| "Unknown".into());
+                streams.push(AudioStream {
+                    index: info.index,
+                    application,
+                    icon_name: info.proplist.get_str("application.icon_name"),
+                    media_name: info.proplist.get_str("media.name"),
+                    sink: info.sink,
+                    volume: volume_percent(&info.volume),
+                    muted: info.mute,
+                    corked: info.corked
+                });
+            },
+            ListResult::End => {
+                let streams = std::mem::take(&mut streams);
+                let playing = streams.iter().any(|s| !s.corked && !s.muted);
+                let _ = tx.send(AudioUpdate::Streams(AudioStreamsStats {
+                    streams,
+                    icon: if playing { "\u{f075a}".into() } else { "\u{f075b}".into() }, // music note / music note off
+                    warn: 0.0
+                }));
+            },
+            ListResult::Error => {}
+        }
+    });
 }
 
-fn read_volume_obj() -> Option<VolumeStats> {
-    // eprint!("Reading volume");
+fn microphone_stats_from_source(info: &SourceInfo) -> MicrophoneStats {
     let config: &Config = Config::global();
-    let volume = read_volume()?;
-    let headphones = read_headphones()?;
 
+    let muted = info.mute;
+    let volume = if muted { 0 } else { volume_percent(&info.volume) };
+    let warn = if muted { 0.0 } else { config.threshold_volume_microphone.get_warn_level(volume as f64) };
+    let icon = if muted { "\u{f036d}" } else { "\u{f036e}" }; // microphone off / microphone outline
+
+    MicrophoneStats {
+        name: info.name.as_ref().map(|n| n.to_string()).unwrap_or_default(),
+        description: info.description.as_ref().map(|d| d.to_string()).unwrap_or_default(),
+        value: volume,
+        muted,
+        in_use: false,
+        recording_apps: Vec::new(),
+        icon: icon.into(),
+        warn
+    }
+}
+
+fn iterate_pulse_mainloop(mainloop: &mut Mainloop, context: &Context) -> Result<(), String> {
+    match mainloop.iterate(true) {
+        IterateResult::Success(_) => {},
+        IterateResult::Quit(_) => return Err("mainloop quit".into()),
+        IterateResult::Err(e) => return Err(format!("mainloop error: {e}"))
+    }
+    match context.get_state() {
+        ContextState::Failed => Err("context failed".into()),
+        ContextState::Terminated => Err("context terminated".into()),
+        _ => Ok(())
+    }
+}
+
+fn volume_percent(volume: &ChannelVolumes) -> i64 {
+    (volume.avg().0 as f64 * 100.0 / Volume::NORMAL.0 as f64).round() as i64
+}
+
+fn sink_port_type(info: &SinkInfo) -> &'static str {
+    let bus = info.proplist.get_str("device.bus").unwrap_or_default();
+    let sink_name = info.name.as_ref().map(|n| n.to_lowercase()).unwrap_or_default();
+    if bus == "bluetooth" || sink_name.starts_with("bluez") {
+        return "bluetooth";
+    }
+
+    let port = info.active_port.as_ref()
+        .map(|p| format!("{} {}",
+            p.name.as_ref().map(|n| n.to_lowercase()).unwrap_or_default(),
+            p.description.as_ref().map(|d| d.to_lowercase()).unwrap_or_default()))
+        .unwrap_or_default();
+    let form_factor = info.proplist.get_str("device.form_factor").unwrap_or_default();
+
+    if port.contains("hdmi") || port.contains("displayport") || sink_name.contains("hdmi") {
+        "hdmi"
+    } else if port.contains("headset") || form_factor == "headset" {
+        "headset"
+    } else if port.contains("headphone") || form_factor == "headphone" {
+        "headphones"
+    } else if bus == "usb" {
+        "usb"
+    } else if port.contains("speaker") || port.contains("analog-output") || form_factor == "speaker" || form_factor == "internal" {
+        "speaker"
+    } else {
+        "unknown"
+    }
+}
+
+fn volume_stats_from_sink(info: &SinkInfo) -> VolumeStats {
+    let config: &Config = Config::global();
+
+    let muted = info.mute;
+    let volume = if muted { 0 } else { volume_percent(&info.volume) };
+    let port_type = sink_port_type(info);
+
+    // Bluetooth properties are named differently by pulseaudio and pipewire-pulse
+    let (bt_profile, bt_codec) = if port_type == "bluetooth" {
+        (
+            info.proplist.get_str("bluetooth.protocol").or_else(|| info.proplist.get_str("api.bluez5.profile")),
+            info.proplist.get_str("bluetooth.codec").or_else(|| info.proplist.get_str("api.bluez5.codec"))
+        )
+    } else {
+        (None, None)
+    };
+
+    let headphones = matches!(port_type, "headphones" | "headset" | "bluetooth");
+
+    let threshold = if headphones { &config.threshold_volume_headphones } else { &config.threshold_volume_speakers };
     let warn = if volume == 0 {
         0.0
-    } else if headphones == 1 {
-        // utils::get_warn_level(20.0, 90.0, volume as f64, false)
-        config.threshold_volume_headphones.get_warn_level(volume as f64)
+    } else if headphones {
+        threshold.get_warn_level(volume as f64)
     } else {
-        config.threshold_volume_speakers.get_warn_level(volume as f64).max(0.4)
+        threshold.get_warn_level(volume as f64).max(0.4)
     };
 
-    Some(VolumeStats {
+    let icon = if muted { "\u{f075f}" } // volume off
+               else { match port_type {
+                   "headphones" => "\u{f02cb}",
+                   "headset" => "\u{f02ce}",
+                   "bluetooth" => "\u{f0970}", // headphones bluetooth
+                   "hdmi" => "\u{f0379}", // monitor
+                   "usb" => "\u{f0553}",
+                   _ => "\u{f057e}" // volume high
+               } };
+
+    VolumeStats {
         value: volume,
-        icon: "".to_string(),
-        color: "".to_string(),
-        clazz: "".to_string(),
+        muted,
+        sink: info.name.as_ref().map(|n| n.to_string()).unwrap_or_default(),
+        description: info.description.as_ref().map(|d| d.to_string()).unwrap_or_default(),
+        port: info.active_port.as_ref().and_then(|p| p.name.as_ref()).map(|n| n.to_string()),
+        port_description: info.active_port.as_ref().and_then(|p| p.description.as_ref()).map(|d| d.to_string()),
+        port_type: port_type.to_string(),
+        bt_profile,
+        bt_codec,
+        balance: info.volume.get_balance(&info.channel_map),
+        icon: icon.to_string(),
+        color: threshold.get_color(volume as f64),
+        clazz: port_type.to_string(),
         warn,
-        headphones,
-    })
+        headphones: if headphones { 1 } else { 0 },
+    }
 }
 
-fn read_volume() -> Option<i64> {
-    let out_mute = Command::new("pactl")
-        .args(["get-sink-mute", "@DEFAULT_SINK@"])
-        .output()
-        .ok()?;
-    let s_mute = String::from_utf8_lossy(&out_mute.stdout);
-    let muted = s_mute.split_whitespace().find(|w| w.ends_with("yes")).is_some();
+// Power profile
+
+static SYSTEM_BUS: Lazy<Option<Connection>> = Lazy::new(|| Connection::system().ok());
+
+// Newer power-profiles-daemon releases use the UPower name, the old one is still exported for compatibility
+const POWER_PROFILES_SERVICES: [(&str, &str); 2] = [
+    ("org.freedesktop.UPower.PowerProfiles", "/org/freedesktop/UPower/PowerProfiles"),
+    ("net.hadess.PowerProfiles", "/net/hadess/PowerProfiles")
+];
+
+fn read_power_profiles_daemon() -> Option<(String, Vec<String>, Option<String>)> {
+    let conn = SYSTEM_BUS.as_ref()?;
+    for (service, path) in POWER_PROFILES_SERVICES {
+        let Ok(reply) = conn.call_method(Some(service), path, Some("org.freedesktop.DBus.Properties"), "GetAll", &service) else { continue };
+        let Ok(properties) = reply.body().deserialize::<HashMap<String, OwnedValue>>() else { continue };
+
+        let Some(active) = properties.get("ActiveProfile").and_then(|v| <&str>::try_from(v).ok()) else { continue };
+        let available = properties.get("Profiles")
+            .and_then(|v| v.try_clone().ok())
+            .and_then(|v| Vec::<HashMap<String, OwnedValue>>::try_from(v).ok())
+            .map(|profiles| profiles.iter()
+                .filter_map(|p| p.get("Profile").and_then(|v| <&str>::try_from(v).ok()).map(String::from))
+                .collect())
+            .unwrap_or_default();
+        let degraded = properties.get("PerformanceDegraded")
+            .and_then(|v| <&str>::try_from(v).ok())
+            .filter(|reason| !reason.is_empty())
+            .map(String::from);
+        return Some((active.to_string(), available, degraded));
+    }
+    None
+}
 
-    if muted {
-        Some(0)
+pub fn get_power_profile() -> Option<PowerProfileStats> {
+    let (active, available, degraded, source) = if let Some((active, available, degraded)) = read_power_profiles_daemon() {
+        (active, available, degraded, "power-profiles-daemon")
     } else {
-        let out = Command::new("pactl")
-            .args(["get-sink-volume", "@DEFAULT_SINK@"])
-            .output()
-            .ok()?;
+        let active = utils::read_sysfs_string("/sys/firmware/acpi/platform_profile")?;
+        let available = utils::read_sysfs_string("/sys/firmware/acpi/platform_profile_choices")
+            .map(|choices| choices.split_whitespace().map(String::from).collect())
+            .unwrap_or_default();
+        (active, available, None, "platform_profile")
+    };
 
-        let s = String::from_utf8_lossy(&out.stdout);
+    // power-profiles-daemon names and the most common platform_profile ones
+    let icon = match active.as_str() {
+        "performance" => "\u{f0463}", // rocket
+        "power-saver" | "low-power" | "quiet" | "cool" => "\u{f032a}", // leaf
+        _ => "\u{f05d1}" // scale balance
+    };
 
-        // estrai percentuale (prima che ti chieda: sì, pactl è un parser testuale dell’inferno)
-        s.split_whitespace()
-            .find(|w| w.ends_with('%'))
-            .and_then(|w| w.trim_end_matches('%').parse::<i64>().ok())
-    }
+    Some(PowerProfileStats {
+        active,
+        available,
+        degraded,
+        source: source.into(),
+        icon: icon.into(),
+        warn: 0.0
+    })
 }
 
-fn read_headphones() -> Option<i8> {
-    let out = Command::new("pactl")
-        .args(["list", "sinks"])
-        .output()
-        .ok()?;
+// Media (MPRIS)
 
-    let s = String::from_utf8_lossy(&out.stdout);
+const MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+const MPRIS_PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
 
-    let plugged = s.lines().any(|l| {
-        l.contains("Active Port") && l.contains("headphones")
+enum MprisEvent {
+    PropertiesChanged(String), // unique name of the player
+    NameOwnerChanged(String, String) // well-known name, new owner (empty when the player quits)
+}
+
+struct MprisPlayer {
+    owner: String,
+    properties: HashMap<String, OwnedValue>,
+    last_active: Instant
+}
+
+pub fn spawn_mpris_listener(tx: Sender<MediaStats>) {
+    thread::spawn(move || {
+        loop {
+            if let Err(e) = run_mpris_listener(&tx) {
+                eprintln!("MPRIS listener: {e}");
+            }
+            thread::sleep(Duration::from_secs(5));
+        }
     });
+}
 
-    Some(if plugged { 1 } else { 0 })
+fn run_mpris_listener(tx: &Sender<MediaStats>) -> zbus::Result<()> {
+    let conn = Connection::session()?;
+
+    let properties_rule = zbus::MatchRule::builder()
+        .msg_type(zbus::message::Type::Signal)
+        .interface("org.freedesktop.DBus.Properties")?
+        .member("PropertiesChanged")?
+        .path(MPRIS_PATH)?
+        .arg(0, MPRIS_PLAYER_INTERFACE)?
+        .build();
+    let owner_rule = zbus::MatchRule::builder()
+        .msg_type(zbus::message::Type::Signal)
+        .sender("org.freedesktop.DBus")?
+        .interface("org.freedesktop.DBus")?
+        .member("NameOwnerChanged")?
+        .arg0ns("org.mpris.MediaPlayer2")?
+        .build();
+
+    // Subscribe before listing the players, so nothing can slip in between
+    let properties_iter = MessageIterator::for_match_rule(properties_rule, &conn, None)?;
+    let owner_iter = MessageIterator::for_match_rule(owner_rule, &conn, None)?;
+
+    // Both signal streams are merged into one channel, the threads end with the connection
+    let (event_tx, event_rx) = std::sync::mpsc::channel::<MprisEvent>();
+    let properties_tx = event_tx.clone();
+    thread::spawn(move || {
+        for msg in properties_iter.flatten() {
+            if let Some(sender) = msg.header().sender() {
+                if properties_tx.send(MprisEvent::PropertiesChanged(sender.to_string())).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    thread::spawn(move || {
+        for msg in owner_iter.flatten() {
+            if let Ok((name, _old_owner, new_owner)) = msg.body().deserialize::<(String, String, String)>() {
+                if event_tx.send(MprisEvent::NameOwnerChanged(name, new_owner)).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    let dbus = zbus::blocking::fdo::DBusProxy::new(&conn)?;
+    let mut players: HashMap<String, MprisPlayer> = HashMap::new();
+    for name in dbus.list_names()? {
+        if !name.starts_with(MPRIS_PREFIX) {
+            continue;
+        }
+        let Ok(owner) = dbus.get_name_owner(name.as_ref()) else { continue };
+        let properties = read_mpris_properties(&conn, &name).unwrap_or_default();
+        players.insert(name.to_string(), MprisPlayer { owner: owner.to_string(), properties, last_active: Instant::now() });
+    }
+    let _ = tx.send(media_stats_from_players(&players));
+
+    for event in event_rx {
+        match event {
+            MprisEvent::PropertiesChanged(sender) => {
+                for (name, player) in players.iter_mut().filter(|(_, p)| p.owner == sender) {
+                    if let Some(properties) = read_mpris_properties(&conn, name) {
+                        player.properties = properties;
+                        player.last_active = Instant::now();
+                    }
+                }
+            },
+            MprisEvent::NameOwnerChanged(name, new_owner) => {
+                if new_owner.is_empty() {
+                    players.remove(&name);
+                } else {
+                    // Players often take the name before exporting MPRIS_PATH, the properties
+                    // are read again at their first PropertiesChanged, matched by owner
+                    let properties = read_mpris_properties(&conn, &name).unwrap_or_default();
+                    players.insert(name, MprisPlayer { owner: new_owner, properties, last_active: Instant::now() });
+                }
+            }
+        }
+        if tx.send(media_stats_from_players(&players)).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn read_mpris_properties(conn: &Connection, name: &str) -> Option<HashMap<String, OwnedValue>> {
+    // Properties are read on every change instead of being merged from the signal,
+    // this way we also get a fresh Position, which is never signalled
+    let reply = conn.call_method(
+        Some(name),
+        MPRIS_PATH,
+        Some("org.freedesktop.DBus.Properties"),
+        "GetAll",
+        &MPRIS_PLAYER_INTERFACE
+    ).ok()?;
+    reply.body().deserialize::<HashMap<String, OwnedValue>>().ok()
+}
+
+fn media_stats_from_players(players: &HashMap<String, MprisPlayer>) -> MediaStats {
+    let status_of = |p: &MprisPlayer| p.properties.get("PlaybackStatus")
+        .and_then(|v| <&str>::try_from(v).ok())
+        .unwrap_or("Stopped")
+        .to_string();
+
+    let mut names: Vec<String> = players.keys().map(|n| n.trim_start_matches(MPRIS_PREFIX).to_string()).collect();
+    names.sort();
+
+    // A playing player always wins, then the most recently active one
+    let active = players.iter().max_by_key(|(_, p)| (status_of(p) == "Playing", p.last_active));
+
+    let Some((name, player)) = active else {
+        return MediaStats {
+            players: names,
+            status: "Stopped".into(),
+            updated: get_unix_time(),
+            icon: "\u{f075b}".into(), // music note off
+            ..Default::default()
+        };
+    };
+
+    let metadata: HashMap<String, OwnedValue> = player.properties.get("Metadata")
+        .and_then(|v| v.try_clone().ok())
+        .and_then(|v| HashMap::<String, OwnedValue>::try_from(v).ok())
+        .unwrap_or_default();
+    let meta_string = |key: &str| metadata.get(key).and_then(|v| <&str>::try_from(v).ok()).map(String::from);
+    let artist = metadata.get("xesam:artist")
+        .and_then(|v| v.try_clone().ok())
+        .and_then(|v| Vec::<String>::try_from(v).ok())
+        .map(|artists| artists.join(", "))
+        .or_else(|| meta_string("xesam:artist"));
+    // Microseconds, signed according to the spec but some players send it unsigned
+    let micros = |v: &OwnedValue| i64::try_from(v).ok().or_else(|| u64::try_from(v).ok().map(|u| u as i64));
+    let length = metadata.get("mpris:length").and_then(micros).map(|us| us as f64 / 1_000_000.0);
+    let position = player.properties.get("Position").and_then(micros).map(|us| us as f64 / 1_000_000.0);
+
+    let status = status_of(player);
+    let icon = match status.as_str() {
+        "Playing" => "\u{f040a}", // play
+        "Paused" => "\u{f03e4}", // pause
+        _ => "\u{f04db}" // stop
+    };
+
+    MediaStats {
+        player: Some(name.trim_start_matches(MPRIS_PREFIX).to_string()),
+        players: names,
+        status,
+        title: meta_string("xesam:title"),
+        artist,
+        album: meta_string("xesam:album"),
+        art_url: meta_string("mpris:artUrl"),
+        position,
+        length,
+        updated: get_unix_time(),
+        icon: icon.into(),
+        warn: 0.0
+    }
 }
 
 // Bluetooth
 
-use zbus::{blocking::Connection, blocking::Proxy};
+use zbus::{blocking::Connection, blocking::MessageIterator, blocking::Proxy};
 
 /* pub fn read_external_batteries() -> zbus::Result<Vec<BatteryDevice>> {
     let conn = Connection::system()?;
@@ -716,8 +2170,6 @@ use zbus::{blocking::Connection, blocking::Proxy};
     Ok(result)
 } */
 
-
-
 /* pub fn print_bt_batteries () {
     match read_external_batteries() {
         Ok(devs) => {
@@ -729,138 +2181,554 @@ use zbus::{blocking::Connection, blocking::Proxy};
     }
 } */
 
-use std::{collections::HashMap};
-use zvariant::OwnedObjectPath;
+use std::collections::{HashMap, VecDeque};
+use zvariant::{OwnedObjectPath, OwnedValue};
+
+/// Full EnumerateDevices resync, to drop devices gone without a DeviceRemoved signal
+const UPOWER_RESYNC_INTERVAL: Duration = Duration::from_secs(60);
 
 pub fn spawn_upower_listener(tx: Sender<BluetoothStats>) {
     thread::spawn(move || {
-        let conn = Connection::system().expect("DBus connection failed");
+        loop {
+            if let Err(e) = run_upower_listener(&tx) {
+                eprintln!("UPower listener: {e}");
+            }
+            thread::sleep(Duration::from_secs(5));
+        }
+    });
+}
 
-        let upower = Proxy::new(
-            &conn,
-            "org.freedesktop.UPower",
-            "/org/freedesktop/UPower",
-            "org.freedesktop.UPower",
-        ).expect("UPower proxy");
+fn run_upower_listener(tx: &Sender<BluetoothStats>) -> zbus::Result<()> {
+    let conn = Connection::system()?;
 
-        let mut devices: HashMap<String, BatteryDevice> = HashMap::new();
+    // Every signal sent by UPower: DeviceAdded/DeviceRemoved on the manager and
+    // PropertiesChanged on each device. Subscribed before the snapshot, so nothing is lost.
+    let rule = zbus::MatchRule::builder()
+        .msg_type(zbus::message::Type::Signal)
+        .sender("org.freedesktop.UPower")?
+        .build();
+    let signals = MessageIterator::for_match_rule(rule, &conn, None)?;
 
-        // Initial snapshot
-        let upowercall: Result<Vec<OwnedObjectPath>, _> = upower.call("EnumerateDevices", &());
-        let mut warn = 0.0;
-        if let Ok(paths) = upowercall {
-            for path in paths {
-                if let Some(dev) = read_device(&conn, &path) {
-                    warn = dev.warn.max(warn);
-                    devices.insert(path.to_string(), dev);
-                }
+    // Signals are forwarded to a channel, so the loop below can wake up periodically to resync
+    let (signal_tx, signal_rx) = std::sync::mpsc::channel::<zbus::Result<zbus::Message>>();
+    thread::spawn(move || {
+        for msg in signals {
+            if signal_tx.send(msg).is_err() {
+                break;
             }
         }
-        let obj = BluetoothStats {
-            devices: devices.values().cloned().collect(),
-            icon: "".to_string(),
-            warn
-        };
-        let _ = tx.send(obj);
-        // println!("devices {:?}", devices);
-
+    });
 
-        // Check updates
-        let mut child = Command::new("upower")
-            .arg("-m")
-            .stdout(Stdio::piped())
-            .spawn()
-            .expect("failed to start upower -m");
+    let upower = Proxy::new(
+        &conn,
+        "org.freedesktop.UPower",
+        "/org/freedesktop/UPower",
+        "org.freedesktop.UPower",
+    )?;
 
-        let stdout = child.stdout.take().expect("no stdout");
-        let reader = BufReader::new(stdout);
+    let mut devices: HashMap<String, BatteryDevice> = HashMap::new();
+
+    // Initial snapshot
+    sync_upower_devices(&conn, &upower, &mut devices)?;
+    let mut last_sync = Instant::now();
+    let mut last = bluetooth_stats(&devices);
+    let _ = tx.send(last.clone());
+
+    // Check updates
+    loop {
+        // Some devices vanish without a DeviceRemoved signal, the list is resynced periodically
+        // even while other devices keep sending signals
+        if last_sync.elapsed() >= UPOWER_RESYNC_INTERVAL {
+            sync_upower_devices(&conn, &upower, &mut devices)?;
+            last_sync = Instant::now();
+            let stats = bluetooth_stats(&devices);
+            if stats != last {
+                if tx.send(stats.clone()).is_err() {
+                    break;
+                }
+                last = stats;
+            }
+        }
 
-        for line in reader.lines() {
-            // println!("{line:?}");
-            let line = match line {
-                Ok(l) => l,
-                Err(_) => break,
-            };
+        let msg = match signal_rx.recv_timeout(UPOWER_RESYNC_INTERVAL.saturating_sub(last_sync.elapsed())) {
+            Ok(msg) => msg?,
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break
+        };
+        let header = msg.header();
+        let (Some(interface), Some(member)) = (header.interface(), header.member()) else { continue };
 
-            // examples:
-            // "device added: /org/freedesktop/UPower/devices/..."
-            // "device changed: /org/freedesktop/UPower/devices/..."
-
-            if let (Some(mut path), Some(evt_type)) = (parse_upower_event(&line), parse_upower_event_type(&line)) {
-                path = path.trim();
-                // println!("trimmed: {path} {evt_type}");
-
-                let mut update = false;
-                if evt_type == "device changed" && devices.contains_key(path) {
-                    update = true;
-                } else if evt_type == "device removed" && devices.contains_key(path) {
-                    devices.remove(path);
-                    update = true;
-                } else if evt_type == "device added" {
-                    update = true;
+        match (interface.as_str(), member.as_str()) {
+            ("org.freedesktop.UPower", "DeviceAdded") => {
+                let Ok(path) = msg.body().deserialize::<OwnedObjectPath>() else { continue };
+                if let Some(dev) = read_device(&conn, path.as_str()) {
+                    devices.insert(path.to_string(), dev);
                 }
-                
-                // println!("Update? {}", update);
-                if update {
-                    let upowercall: Result<Vec<OwnedObjectPath>, _> = upower.call("EnumerateDevices", &());
-                    if let Ok(paths) = upowercall {
-                        for path in paths {
-                            if let Some(dev) = read_device(&conn, &path) {
-                                devices.insert(path.to_string(), dev);
-                            }
-                        }
-                    }
-                    // println!("devices {:?}", devices);
-                    let obj = BluetoothStats {
-                        devices: devices.values().cloned().collect(),
-                        icon: "".to_string(),
-                        warn: 0.0
-                    };
-                    let _ = tx.send(obj);
-                    // println!("devices2222222222 {:?}", devices);
+            },
+            ("org.freedesktop.UPower", "DeviceRemoved") => {
+                let Ok(path) = msg.body().deserialize::<OwnedObjectPath>() else { continue };
+                devices.remove(path.as_str());
+            },
+            ("org.freedesktop.DBus.Properties", "PropertiesChanged") => {
+                let Some(path) = header.path().map(|p| p.to_string()) else { continue };
+                let Ok((changed_interface, _, _)) = msg.body().deserialize::<(String, HashMap<String, OwnedValue>, Vec<String>)>() else { continue };
+                if changed_interface != "org.freedesktop.UPower.Device" {
+                    continue;
                 }
+                // Only the device that changed is read again
+                match read_device(&conn, &path) {
+                    Some(dev) => { devices.insert(path, dev); },
+                    // Not present anymore, or not a peripheral battery
+                    None => { devices.remove(&path); }
+                }
+            },
+            _ => continue
+        };
+
+        let stats = bluetooth_stats(&devices);
+        if stats != last {
+            if tx.send(stats.clone()).is_err() {
+                break;
             }
+            last = stats;
         }
-    });
+    }
+    Ok(())
 }
 
-fn parse_upower_event(line: &str) -> Option<&str> {
-    line.split(": ").nth(1)
+/// Aligns `devices` with EnumerateDevices: new paths are read, missing ones are dropped
+fn sync_upower_devices(conn: &Connection, upower: &Proxy, devices: &mut HashMap<String, BatteryDevice>) -> zbus::Result<()> {
+    let paths: Vec<OwnedObjectPath> = upower.call("EnumerateDevices", &())?;
+    let mut current: HashMap<String, BatteryDevice> = HashMap::new();
+    for path in paths {
+        if let Some(dev) = read_device(conn, path.as_str()) {
+            current.insert(path.to_string(), dev);
+        }
+    }
+    *devices = current;
+    Ok(())
 }
-fn parse_upower_event_type(line: &str) -> Option<&str> {
-    if let Some(cmd) = line.split(": ").nth(0) {
-        return cmd.split("\t").nth(1)
+
+fn bluetooth_stats(devices: &HashMap<String, BatteryDevice>) -> BluetoothStats {
+    let config: &Config = Config::global();
+    let mut list: Vec<BatteryDevice> = devices.values().cloned().collect();
+    list.sort_by(|a, b| a.name.cmp(&b.name));
+    // The device driving the warning, its icon is the one shown
+    let critical = list.iter()
+        .filter(|d| match &config.bluetooth_warn_device {
+            // Only the critical device counts, no warning while it is disconnected
+            Some(name) => &d.name == name || &d.model == name,
+            None => true
+        })
+        .fold(None, |acc: Option<&BatteryDevice>, d| match acc {
+            Some(best) if best.warn >= d.warn => Some(best),
+            _ => Some(d)
+        });
+    let (icon, warn) = critical.map(|d| (d.icon.clone(), d.warn)).unwrap_or_default();
+    BluetoothStats {
+        devices: list,
+        icon,
+        warn
     }
-    return None
 }
 
+/// Default icon for each kind, overridable with bluetooth_icons
+fn device_kind_icon(kind: &UPowerDeviceKind) -> &'static str {
+    match kind {
+        UPowerDeviceKind::Mouse => "\u{f037d}", // mouse
+        UPowerDeviceKind::Keyboard => "\u{f030c}", // keyboard
+        UPowerDeviceKind::Headset => "\u{f02ce}", // headset
+        UPowerDeviceKind::Headphones => "\u{f02cb}", // headphones
+        UPowerDeviceKind::Speakers | UPowerDeviceKind::OtherAudio => "\u{f04c3}", // speaker
+        UPowerDeviceKind::GamingInput => "\u{f0297}", // gamepad variant
+        UPowerDeviceKind::Phone => "\u{f011c}", // cellphone
+        UPowerDeviceKind::Tablet => "\u{f04f6}", // tablet
+        UPowerDeviceKind::Computer => "\u{f0322}", // laptop
+        UPowerDeviceKind::Pen => "\u{f03eb}", // pencil
+        UPowerDeviceKind::Wearable => "\u{f0589}", // watch
+        UPowerDeviceKind::RemoteControl => "\u{f0454}", // remote
+        UPowerDeviceKind::Camera => "\u{f0100}", // camera
+        UPowerDeviceKind::Printer => "\u{f042a}", // printer
+        _ => "\u{f00af}" // bluetooth
+    }
+}
 
-fn read_device(conn: &Connection, path: &OwnedObjectPath) -> Option<BatteryDevice> {
-    let dev = Proxy::new(
-        conn,
-        "org.freedesktop.UPower",
-        path.as_str(),
-        "org.freedesktop.UPower.Device",
+fn read_device(conn: &Connection, path: &str) -> Option<BatteryDevice> {
+    let reply = conn.call_method(
+        Some("org.freedesktop.UPower"),
+        path,
+        Some("org.freedesktop.DBus.Properties"),
+        "GetAll",
+        &"org.freedesktop.UPower.Device"
     ).ok()?;
+    let properties: HashMap<String, OwnedValue> = reply.body().deserialize().ok()?;
 
-    let is_present: bool = dev.get_property("IsPresent").ok()?;
-    let power_supply: bool = dev.get_property("PowerSupply").ok()?;
-    let dev_type: u32 = dev.get_property("Type").ok()?;
+    let is_present = properties.get("IsPresent").and_then(|v| bool::try_from(v).ok())?;
+    let power_supply = properties.get("PowerSupply").and_then(|v| bool::try_from(v).ok())?;
+    let dev_type = properties.get("Type").and_then(|v| u32::try_from(v).ok())?;
 
     if !is_present || power_supply || dev_type == 1 || dev_type == 2 {
         return None;
     }
 
-    let percentage: f64 = dev.get_property("Percentage").ok()?;
-    let model: String = dev.get_property("Model").unwrap_or_else(|_| "Unknown".into());
-    
+    let percentage = properties.get("Percentage").and_then(|v| f64::try_from(v).ok())?;
+    let model = properties.get("Model")
+        .and_then(|v| <&str>::try_from(v).ok())
+        .map(String::from)
+        .unwrap_or_else(|| "Unknown".into());
+
+    let native_path = properties.get("NativePath")
+        .and_then(|v| <&str>::try_from(v).ok())
+        .map(String::from)
+        .unwrap_or_default();
+    let serial = properties.get("Serial")
+        .and_then(|v| <&str>::try_from(v).ok())
+        .map(String::from)
+        .unwrap_or_default();
+
+    let kind = UPowerDeviceKind::from(dev_type);
     let config: &Config = Config::global();
-    let warn = config.threshold_bluetooth_battery.get_warn_level(percentage);
+    let warn = config.bluetooth_battery_threshold(&kind).get_warn_level(percentage);
+
+    // Aliases are looked up by serial (usually the bluetooth address), native path, then model
+    let name = [&serial, &native_path, &model].into_iter()
+        .filter(|key| !key.is_empty())
+        .find_map(|key| config.bluetooth_aliases.get(key.as_str()))
+        .cloned()
+        .unwrap_or_else(|| model.clone());
+    let icon = config.bluetooth_icons.get(&format!("{:?}", kind))
+        .cloned()
+        .unwrap_or_else(|| device_kind_icon(&kind).to_string());
 
     Some(BatteryDevice {
-        name: model,
-        kind: UPowerDeviceKind::from(dev_type),
+        name,
+        model,
+        native_path,
+        serial,
+        kind,
+        icon,
         percentage,
         warn
     })
 }
+
+const BLUEZ_SERVICE: &str = "org.bluez";
+
+type ManagedObjects = HashMap<OwnedObjectPath, HashMap<String, HashMap<String, OwnedValue>>>;
+
+pub fn spawn_bluez_listener(tx: Sender<BluezStats>) {
+    thread::spawn(move || {
+        loop {
+            if let Err(e) = run_bluez_listener(&tx) {
+                eprintln!("BlueZ listener: {e}");
+                // Probably bluetoothd is not running
+                let _ = tx.send(BluezStats { icon: "\u{f00b2}".into(), ..Default::default() }); // bluetooth off
+            }
+            thread::sleep(Duration::from_secs(5));
+        }
+    });
+}
+
+fn run_bluez_listener(tx: &Sender<BluezStats>) -> zbus::Result<()> {
+    let conn = Connection::system()?;
+
+    // InterfacesAdded/InterfacesRemoved from the ObjectManager and PropertiesChanged
+    // on adapters, devices and batteries
+    let bluez_rule = zbus::MatchRule::builder()
+        .msg_type(zbus::message::Type::Signal)
+        .sender(BLUEZ_SERVICE)?
+        .build();
+    let owner_rule = zbus::MatchRule::builder()
+        .msg_type(zbus::message::Type::Signal)
+        .sender("org.freedesktop.DBus")?
+        .interface("org.freedesktop.DBus")?
+        .member("NameOwnerChanged")?
+        .arg(0, BLUEZ_SERVICE)?
+        .build();
+    let bluez_iter = MessageIterator::for_match_rule(bluez_rule, &conn, None)?;
+    let owner_iter = MessageIterator::for_match_rule(owner_rule, &conn, None)?;
+
+    // Every signal means "read the objects again", so both streams are merged into one channel
+    let (event_tx, event_rx) = std::sync::mpsc::channel::<()>();
+    let owner_tx = event_tx.clone();
+    thread::spawn(move || {
+        for _ in bluez_iter.flatten() {
+            if event_tx.send(()).is_err() {
+                break;
+            }
+        }
+    });
+    thread::spawn(move || {
+        for _ in owner_iter.flatten() {
+            if owner_tx.send(()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut last = read_bluez_stats(&conn);
+    let _ = tx.send(last.clone());
+
+    while event_rx.recv().is_ok() {
+        // Signals come in bursts (e.g. a connection changes several properties at once)
+        while event_rx.try_recv().is_ok() {}
+
+        let stats = read_bluez_stats(&conn);
+        if stats != last {
+            if tx.send(stats.clone()).is_err() {
+                break;
+            }
+            last = stats;
+        }
+    }
+    Ok(())
+}
+
+fn read_bluez_stats(conn: &Connection) -> BluezStats {
+    let Ok(reply) = conn.call_method(
+        Some(BLUEZ_SERVICE),
+        "/",
+        Some("org.freedesktop.DBus.ObjectManager"),
+        "GetManagedObjects",
+        &()
+    ) else {
+        return BluezStats { icon: "\u{f00b2}".into(), ..Default::default() }; // bluetooth off
+    };
+    let Ok(objects) = reply.body().deserialize::<ManagedObjects>() else {
+        return BluezStats { icon: "\u{f00b2}".into(), ..Default::default() };
+    };
+    bluez_stats_from_objects(&objects)
+}
+
+fn bluez_stats_from_objects(objects: &ManagedObjects) -> BluezStats {
+    let config: &Config = Config::global();
+    let get_str = |props: &HashMap<String, OwnedValue>, key: &str| -> Option<String> {
+        props.get(key).and_then(|v| <&str>::try_from(v).ok()).map(String::from)
+    };
+    let get_bool = |props: &HashMap<String, OwnedValue>, key: &str| -> bool {
+        props.get(key).and_then(|v| bool::try_from(v).ok()).unwrap_or(false)
+    };
+
+    let mut paths: Vec<&OwnedObjectPath> = objects.keys().collect();
+    paths.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+
+    // First adapter by path, usually hci0
+    let adapter = paths.iter().find_map(|path| {
+        objects[*path].get("org.bluez.Adapter1").map(|props| (path.as_str(), props))
+    });
+
+    let mut devices: Vec<BluezDevice> = Vec::new();
+    for path in &paths {
+        let interfaces = &objects[*path];
+        let Some(props) = interfaces.get("org.bluez.Device1") else { continue };
+        let connected = get_bool(props, "Connected");
+        if !connected {
+            continue;
+        }
+        // Devices belonging to another adapter are skipped
+        if let Some((adapter_path, _)) = adapter {
+            if !path.as_str().starts_with(&format!("{adapter_path}/")) {
+                continue;
+            }
+        }
+
+        let address = get_str(props, "Address").unwrap_or_default();
+        let name = get_str(props, "Alias")
+            .or_else(|| get_str(props, "Name"))
+            .unwrap_or_else(|| address.clone());
+        let battery = interfaces.get("org.bluez.Battery1")
+            .and_then(|battery| battery.get("Percentage"))
+            .and_then(|v| u8::try_from(v).ok());
+        let warn = battery
+            .map(|b| config.threshold_bluetooth_battery.get_warn_level(b as f64))
+            .unwrap_or(0.0);
+
+        devices.push(BluezDevice {
+            address,
+            name,
+            icon: get_str(props, "Icon"),
+            class: props.get("Class").and_then(|v| u32::try_from(v).ok()),
+            rssi: props.get("RSSI").and_then(|v| i16::try_from(v).ok()),
+            battery,
+            connected,
+            warn
+        });
+    }
+
+    let powered = adapter.map(|(_, props)| get_bool(props, "Powered")).unwrap_or(false);
+    let icon = if !powered { "\u{f00b2}" } // bluetooth off
+        else if !devices.is_empty() { "\u{f00b1}" } // bluetooth connect
+        else { "\u{f00af}" }; // bluetooth
+    let warn = devices.iter().map(|d| d.warn).fold(0.0, f64::max);
+
+    BluezStats {
+        adapter: adapter.and_then(|(path, _)| path.rsplit('/').next()).map(String::from),
+        address: adapter.and_then(|(_, props)| get_str(props, "Address")),
+        powered,
+        discoverable: adapter.map(|(_, props)| get_bool(props, "Discoverable")).unwrap_or(false),
+        devices,
+        icon: icon.into(),
+        warn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, TimeZone, Utc};
+
+    // Battery ETA of several packs
+
+    fn pack(energy: f32, capacity: f32, eta: Option<f32>) -> BatteryUnit {
+        BatteryUnit { energy, capacity, eta, ..Default::default() }
+    }
+
+    #[test]
+    fn single_pack_uses_firmware_eta() {
+        let units = [pack(36000.0, 180000.0, Some(42.0))];
+        assert_eq!(combined_battery_eta(&units, State::Discharging, 36000.0, 180000.0, 10.0), Some(42.0));
+    }
+
+    #[test]
+    fn two_packs_discharging() {
+        // 72 kJ (20 Wh) at 10 W: 7200 s, 120 minutes
+        let units = [pack(36000.0, 180000.0, None), pack(36000.0, 180000.0, None)];
+        let eta = combined_battery_eta(&units, State::Discharging, 72000.0, 360000.0, 10.0).unwrap();
+        assert!((eta - 120.0).abs() < 1e-3, "{eta}");
+    }
+
+    #[test]
+    fn two_packs_charging() {
+        // 288 kJ missing at 10 W: 28800 s, 480 minutes
+        let units = [pack(36000.0, 180000.0, None), pack(36000.0, 180000.0, None)];
+        let eta = combined_battery_eta(&units, State::Charging, 72000.0, 360000.0, 10.0).unwrap();
+        assert!((eta - 480.0).abs() < 1e-3, "{eta}");
+    }
+
+    #[test]
+    fn two_packs_without_power() {
+        let units = [pack(36000.0, 180000.0, None), pack(36000.0, 180000.0, None)];
+        assert_eq!(combined_battery_eta(&units, State::Discharging, 72000.0, 360000.0, 0.0), None);
+        assert_eq!(combined_battery_eta(&units, State::Full, 72000.0, 360000.0, 10.0), None);
+    }
+
+    // Battery energy regression
+
+    // Energy in J every 10 s, changing by watt * 10 J each sample
+    fn linear_series(start: f32, watt: f32, samples: usize) -> VecDeque<(f64, f32)> {
+        (0..samples).map(|i| (1_700_000_000.0 + 10.0 * i as f64, start + watt * 10.0 * i as f32)).collect()
+    }
+
+    #[test]
+    fn slope_of_linear_series_is_the_power() {
+        let slope = energy_slope(&linear_series(100000.0, -12.5, 30)).unwrap();
+        assert!((slope + 12.5).abs() < 1e-3, "{slope}");
+        let slope = energy_slope(&linear_series(100000.0, 30.0, 30)).unwrap();
+        assert!((slope - 30.0).abs() < 1e-3, "{slope}");
+    }
+
+    #[test]
+    fn slope_needs_two_instants() {
+        assert_eq!(energy_slope(&VecDeque::new()), None);
+        assert_eq!(energy_slope(&linear_series(100000.0, -10.0, 1)), None);
+    }
+
+    #[test]
+    fn eta_from_known_slope() {
+        // 72 kJ at 10 W: 120 minutes, 288 kJ missing at 20 W: 240 minutes
+        assert_eq!(eta_from_slope(72000.0, 360000.0, State::Discharging, -10.0), Some(120.0));
+        assert_eq!(eta_from_slope(72000.0, 360000.0, State::Charging, 20.0), Some(240.0));
+    }
+
+    #[test]
+    fn eta_ignores_slope_against_the_state() {
+        assert_eq!(eta_from_slope(72000.0, 360000.0, State::Discharging, 5.0), None);
+        assert_eq!(eta_from_slope(72000.0, 360000.0, State::Charging, -5.0), None);
+        assert_eq!(eta_from_slope(72000.0, 360000.0, State::Full, -5.0), None);
+    }
+
+    // Sun
+
+    fn unix(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> f64 {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap().timestamp() as f64
+    }
+
+    #[test]
+    fn declination_at_solstices_and_equinox() {
+        let june = solar_position(julian_century(unix(2026, 6, 21, 8, 24)));
+        assert!((june.declination - 23.44).abs() < 0.01, "{}", june.declination);
+        let december = solar_position(julian_century(unix(2026, 12, 21, 20, 50)));
+        assert!((december.declination + 23.44).abs() < 0.01, "{}", december.declination);
+        let march = solar_position(julian_century(unix(2026, 3, 20, 14, 46)));
+        assert!(march.declination.abs() < 0.01, "{}", march.declination);
+    }
+
+    #[test]
+    fn equation_of_time_extremes() {
+        // About +16.4 minutes early in November, -14.2 minutes in February
+        let november = solar_position(julian_century(unix(2026, 11, 3, 12, 0)));
+        assert!((november.eq_time - 16.4).abs() < 0.1, "{}", november.eq_time);
+        let february = solar_position(julian_century(unix(2026, 2, 11, 12, 0)));
+        assert!((february.eq_time + 14.2).abs() < 0.1, "{}", february.eq_time);
+    }
+
+    #[test]
+    fn hour_angle_at_the_equator() {
+        // Sun on the equator: geometric sunrise six hours before noon
+        assert!((hour_angle(0.0, 0.0, 90.0).unwrap() - 90.0).abs() < 1e-9);
+        assert!((hour_angle(0.0, 0.0, ZENITH_SUNRISE).unwrap() - ZENITH_SUNRISE).abs() < 1e-9);
+    }
+
+    #[test]
+    fn hour_angle_polar_day_and_night() {
+        assert_eq!(hour_angle(78.0, 23.44, ZENITH_SUNRISE), None);
+        assert_eq!(hour_angle(78.0, -23.44, ZENITH_SUNRISE), None);
+        assert_eq!(hour_angle(-78.0, -23.44, ZENITH_SUNRISE), None);
+        assert!(hour_angle(60.0, 23.44, ZENITH_SUNRISE).is_some());
+    }
+
+    #[test]
+    fn desenzano_in_october() {
+        let rome = FixedOffset::east_opt(2 * 3600).unwrap();
+        let now = rome.with_ymd_and_hms(2026, 10, 19, 12, 0, 0).unwrap();
+        let sun = sun_stats(45.457692, 10.570684, &now).unwrap();
+        assert_eq!(sun.sunrise.as_deref(), Some("07:39"));
+        assert_eq!(sun.sunset.as_deref(), Some("18:25"));
+        assert_eq!(sun.solar_noon, "13:02");
+        assert_eq!(sun.civil_dawn.as_deref(), Some("07:09"));
+        assert_eq!(sun.civil_dusk.as_deref(), Some("18:55"));
+        assert_eq!(sun.nautical_dawn.as_deref(), Some("06:34"));
+        assert_eq!(sun.nautical_dusk.as_deref(), Some("19:30"));
+        assert_eq!(sun.sunrise_mins, Some(7 * 60 + 39));
+        assert!(sun.day_length.abs_diff(646) <= 1, "{}", sun.day_length);
+        assert!(sun.is_day);
+        assert_eq!(sun.phase, "day");
+        assert!(sun.elevation > 30.0 && sun.elevation < 35.0, "{}", sun.elevation);
+    }
+
+    #[test]
+    fn polar_day() {
+        let now = Utc.with_ymd_and_hms(2026, 6, 21, 12, 0, 0).unwrap();
+        let sun = sun_stats(78.22, 15.65, &now).unwrap();
+        assert_eq!(sun.sunrise, None);
+        assert_eq!(sun.sunset, None);
+        assert_eq!(sun.civil_dawn, None);
+        assert!(sun.is_day);
+        assert_eq!(sun.day_length, 1440);
+        assert!((sun.daylight - 0.5).abs() < 1e-9);
+        assert_eq!(sun.phase, "day");
+    }
+
+    #[test]
+    fn polar_night() {
+        let now = Utc.with_ymd_and_hms(2026, 12, 21, 12, 0, 0).unwrap();
+        let sun = sun_stats(78.22, 15.65, &now).unwrap();
+        assert_eq!(sun.sunrise, None);
+        assert_eq!(sun.sunset, None);
+        assert_eq!(sun.civil_dawn, None);
+        assert!(!sun.is_day);
+        assert_eq!(sun.day_length, 0);
+        assert_eq!(sun.daylight, 0.0);
+        assert!(sun.elevation < -6.0, "{}", sun.elevation);
+    }
+}
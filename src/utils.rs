@@ -62,6 +62,14 @@ pub fn get_color_gradient(min: f64, max: f64, value: f64, reversed: bool) -> Str
     format!("#{:02X}{:02X}{:02X}", r, g, b)
 }
 
+pub fn read_sysfs_string(path: impl AsRef<Path>) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+pub fn read_sysfs_value<T: std::str::FromStr>(path: impl AsRef<Path>) -> Option<T> {
+    read_sysfs_string(path)?.parse::<T>().ok()
+}
+
 pub fn write_json_atomic<P: AsRef<Path>>(path: P, stats: &SystemStats) -> std::io::Result<()> {
     let tmp_path = path.as_ref().with_extension("tmp");
 
@@ -94,9 +102,8 @@ pub fn write_niri_json_atomic<P: AsRef<Path>>(path: P, stats: &niri_ipc::state::
     let tmp_path = path.as_ref().with_extension("tmp");
 
     let mut icons: HashMap<String, Option<PathBuf>> = HashMap::new();
-    stats.windows.windows.clone().into_iter().for_each(|(_, w)| {
-        let appid = w.app_id.unwrap();
-        let iconpath = lookup(&appid)/*.with_theme("Adwaita")*/.with_cache().find();
+    stats.windows.windows.values().filter_map(|w| w.app_id.as_ref()).for_each(|appid| {
+        let iconpath = resolve_icon(appid, None, None);
         // println!("{} {:?}", appid, iconpath);
         icons.insert(appid.clone(), iconpath);
     });
@@ -118,6 +125,115 @@ pub fn write_niri_json_atomic<P: AsRef<Path>>(path: P, stats: &niri_ipc::state::
     Ok(())
 }
 
+// Found icons survive restarts, missing ones are retried after ICON_MISSING_TTL (the app may be installed later)
+struct IconCache {
+    found: HashMap<String, PathBuf>,
+    missing: HashMap<String, std::time::Instant>
+}
+
+// The niri listener resolves every window at every event, .desktop files are not scanned that often
+const ICON_MISSING_TTL: std::time::Duration = std::time::Duration::from_secs(60);
+
+static ICON_CACHE: once_cell::sync::Lazy<std::sync::Mutex<IconCache>> = once_cell::sync::Lazy::new(|| {
+    let found = fs::read_to_string(icon_cache_path())
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default();
+    std::sync::Mutex::new(IconCache { found, missing: Default::default() })
+});
+
+fn icon_cache_path() -> PathBuf {
+    PathBuf::from(shellexpand::tilde("~/.cache/ratatoskr/icons.json").as_ref())
+}
+
+/// Icon path for an icon name or app id. Tries the name as is, its lowercase variant,
+/// then the Icon of the .desktop file whose name or StartupWMClass matches
+pub fn resolve_icon(name: &str, size: Option<u16>, theme: Option<&str>) -> Option<PathBuf> {
+    let key = format!("{}|{}|{}", name, size.unwrap_or_default(), theme.unwrap_or_default());
+    if let Ok(cache) = ICON_CACHE.lock() {
+        if let Some(path) = cache.found.get(&key).filter(|p| p.exists()) {
+            return Some(path.clone());
+        }
+        if cache.missing.get(&key).is_some_and(|t| t.elapsed() < ICON_MISSING_TTL) {
+            return None;
+        }
+    }
+
+    let find = |icon: &str| {
+        let mut builder = lookup(icon).with_cache();
+        if let Some(size) = size { builder = builder.with_size(size); }
+        if let Some(theme) = theme { builder = builder.with_theme(theme); }
+        builder.find()
+    };
+    let lowercase = name.to_lowercase();
+    let path = find(name)
+        .or_else(|| if lowercase != name { find(&lowercase) } else { None })
+        .or_else(|| {
+            let icon = desktop_entry_icon(name)?;
+            let icon_path = PathBuf::from(&icon);
+            if icon_path.is_absolute() { icon_path.exists().then_some(icon_path) } else { find(&icon) }
+        });
+
+    if let Ok(mut cache) = ICON_CACHE.lock() {
+        match &path {
+            Some(p) => {
+                cache.missing.remove(&key);
+                cache.found.insert(key, p.clone());
+                if let Err(e) = write_icon_cache(&cache.found) {
+                    eprintln!("Failed to write icon cache: {e}");
+                }
+            },
+            None => { cache.missing.insert(key, std::time::Instant::now()); }
+        }
+    }
+    path
+}
+
+fn write_icon_cache(found: &HashMap<String, PathBuf>) -> std::io::Result<()> {
+    let path = icon_cache_path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, serde_json::to_string(found)?)?;
+    fs::rename(tmp_path, path)
+}
+
+/// Icon key of the .desktop file named after the app id or with a matching StartupWMClass
+fn desktop_entry_icon(app_id: &str) -> Option<String> {
+    let data_home = std::env::var("XDG_DATA_HOME")
+        .unwrap_or_else(|_| shellexpand::tilde("~/.local/share").into_owned());
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .unwrap_or_else(|_| "/usr/local/share:/usr/share".into());
+
+    for dir in std::iter::once(data_home.as_str()).chain(data_dirs.split(':')) {
+        let Ok(entries) = fs::read_dir(Path::new(dir).join("applications")) else { continue };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("desktop") {
+                continue;
+            }
+            let Ok(content) = fs::read_to_string(&path) else { continue };
+            // Only the main section, actions have their own keys
+            let main_section = content.split("\n[").next().unwrap_or_default();
+            let value = |key: &str| main_section.lines()
+                .find_map(|l| l.strip_prefix(key).and_then(|v| v.strip_prefix('=')))
+                .map(|v| v.trim().to_string());
+
+            let stem_matches = path.file_stem()
+                .and_then(|s| s.to_str())
+                .is_some_and(|s| s.eq_ignore_ascii_case(app_id));
+            let class_matches = value("StartupWMClass").is_some_and(|c| c.eq_ignore_ascii_case(app_id));
+            if stem_matches || class_matches {
+                if let Some(icon) = value("Icon") {
+                    return Some(icon);
+                }
+            }
+        }
+    }
+    None
+}
+
 use std::fs::OpenOptions;
 // use std::io::Write;
 